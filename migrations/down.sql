@@ -1 +1,6 @@
+drop table if exists idempotency_keys;
+drop table if exists index_events;
+drop table if exists index_dependencies;
+drop index if exists indexes_active_job;
 drop table if exists indexes;
+drop table if exists bundles;
@@ -1,3 +1,12 @@
+create table if not exists bundles (
+  bundle_id integer not null primary key autoincrement,
+  region text not null,
+  profile text not null,
+  status text not null default 'NotAvailable',
+  created_at integer not null default (strftime('%s', 'now')),
+  updated_at integer not null default (strftime('%s', 'now'))
+);
+
 create table if not exists indexes (
   index_id integer not null primary key autoincrement,
   index_type text not null,
@@ -8,3 +17,54 @@ create table if not exists indexes (
   updated_at integer not null default (strftime('%s', 'now'))
 );
 
+-- The columns added to indexes since. sqlite3 has no 'add column if not exists': on a database
+-- which already has a column, it reports a duplicate column, and goes on with the next statement.
+alter table indexes add column metadata text;
+alter table indexes add column options text;
+alter table indexes add column es_index text;
+alter table indexes add column es_alias text;
+alter table indexes add column progress text;
+alter table indexes add column provenance text;
+-- Indexes existing before the column are done with: they must not be active, or the unique index
+-- on active jobs below could not be created.
+alter table indexes add column active integer not null default 0;
+alter table indexes add column bundle_id integer references bundles(bundle_id);
+
+-- For listing indexes, filtered and sorted.
+create index if not exists indexes_index_type_region on indexes(index_type, region);
+create index if not exists indexes_data_source on indexes(data_source);
+create index if not exists indexes_region on indexes(region);
+create index if not exists indexes_state_type on indexes(json_extract(status, '$.type'));
+create index if not exists indexes_created_at on indexes(created_at, index_id);
+create index if not exists indexes_updated_at on indexes(updated_at, index_id);
+
+-- Only one job at a time for an index type, a data source and a region.
+create unique index if not exists indexes_active_job on indexes(index_type, data_source, region) where active = 1;
+
+create table if not exists index_dependencies (
+  index_id integer not null references indexes(index_id) on delete cascade,
+  dependency_id integer not null references indexes(index_id) on delete cascade,
+  primary key (index_id, dependency_id)
+);
+
+
+-- The states an index went through, each time its state changed.
+create table if not exists index_events (
+  event_id integer not null primary key autoincrement,
+  index_id integer not null references indexes(index_id) on delete cascade,
+  status text not null,
+  created_at integer not null default (strftime('%s', 'now'))
+);
+
+create index if not exists index_events_index_id on index_events(index_id, event_id);
+
+-- The keys given by clients to createIndex, so that a retried request does not create another
+-- index. They are kept for a retention window.
+create table if not exists idempotency_keys (
+  idempotency_key text not null primary key,
+  index_id integer not null references indexes(index_id) on delete cascade,
+  request text not null,
+  created_at integer not null default (strftime('%s', 'now'))
+);
+
+create index if not exists idempotency_keys_created_at on idempotency_keys(created_at);
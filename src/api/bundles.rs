@@ -0,0 +1,332 @@
+use futures::TryFutureExt;
+use juniper::GraphQLObject;
+use serde::Serialize;
+use slog::info;
+use snafu::ResultExt;
+use sqlx::Connection;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use crate::api::gql::Context;
+use crate::api::indexes::{
+    all_indexes_db, insert_plan, plan_index, ready_fsm, start, update_db, ConflictPolicy, Creation,
+    IndexRequestBody, UNKNOWN_REGION,
+};
+use crate::api::model::*;
+use crate::db::model::{EntityId, ProvideData};
+use crate::db::Db;
+use crate::error;
+use crate::fsm;
+
+// The statuses of a bundle stored in the database. Once running, the status of the bundle is
+// derived from the status of its indexes.
+const DOWNLOADING: &str = "DownloadingInProgress";
+const DOWNLOADING_ERROR: &str = "DownloadingError";
+const RUNNING: &str = "Running";
+
+/// The response body for a single region bundle
+#[derive(Debug, Serialize, GraphQLObject)]
+pub struct BundleResponseBody {
+    bundle: Bundle,
+}
+
+/// The response body for multiple region bundles
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct MultBundlesResponseBody {
+    bundles: Vec<Bundle>,
+    bundles_count: i32,
+}
+
+impl From<Vec<Bundle>> for MultBundlesResponseBody {
+    fn from(bundles: Vec<Bundle>) -> Self {
+        let bundles_count = i32::try_from(bundles.len()).unwrap();
+        Self {
+            bundles,
+            bundles_count,
+        }
+    }
+}
+
+/// Retrieve all region bundles, along with their indexes
+pub async fn list_bundles(context: &Context) -> Result<MultBundlesResponseBody, error::Error> {
+    async move {
+        let pool = &context.state.pool;
+
+        let mut tx = pool
+            .conn()
+            .and_then(Connection::begin)
+            .await
+            .context(error::DBError {
+                details: "could not retrieve bundles",
+            })?;
+
+        let entities = tx.get_all_bundles().await.context(error::DBProvideError {
+            details: "Could not get all bundles",
+        })?;
+
+        tx.commit().await.context(error::DBError {
+            details: "could not retrieve bundles",
+        })?;
+
+        let mut indexes = all_indexes_db(context).await?;
+
+        let bundles = entities
+            .into_iter()
+            .map(|entity| {
+                let bundle_id = entity.bundle_id;
+                let (children, others): (Vec<Index>, Vec<Index>) = indexes
+                    .drain(..)
+                    .partition(|index| index.bundle_id == Some(bundle_id));
+                indexes = others;
+                with_indexes(Bundle::from(entity), children)
+            })
+            .collect::<Vec<_>>();
+
+        Ok(MultBundlesResponseBody::from(bundles))
+    }
+    .await
+}
+
+/// Create all the indexes listed in a profile for a region
+pub async fn create_region_bundle(
+    region: String,
+    profile: String,
+    context: &Context,
+) -> Result<BundleResponseBody, error::Error> {
+    async move {
+        info!(
+            context.state.logger,
+            "Creating Region Bundle {} {}", region, profile
+        );
+
+        let items =
+            context
+                .state
+                .settings
+                .profiles
+                .get(&profile)
+                .ok_or(error::Error::MiscError {
+                    details: format!("Unknown profile '{}'", profile),
+                })?;
+
+        // We check all the indexes of the profile the way createIndex does, before creating
+        // anything in the database. Each data source gets the bundle region in its own names,
+        // which may be several regions (eg the departments of a region for BANO).
+        let mut plans = Vec::new();
+        for (position, item) in items.iter().enumerate() {
+            let position = position.to_string();
+            let regions = match context
+                .state
+                .registry
+                .find(&item.data_source, &item.index_type)
+            {
+                Ok(source) => source.bundle_regions(&region),
+                // What is wrong with the data source is reported when planning the index.
+                Err(_) => vec![region.clone()],
+            };
+            if regions.is_empty() {
+                let violation = error::Violation {
+                    code: UNKNOWN_REGION,
+                    path: vec![String::from("region")],
+                    message: format!("{} has no region in {}", item.data_source, region),
+                };
+                return Err(
+                    error::Error::validation(vec![violation]).within(&["profile", &position])
+                );
+            }
+            for item_region in regions {
+                let index_request = IndexRequestBody {
+                    index_type: item.index_type.clone(),
+                    data_source: item.data_source.clone(),
+                    region: item_region,
+                    file_path: None,
+                    url: None,
+                    format: None,
+                    options: None,
+                    on_conflict: Some(ConflictPolicy::Reject),
+                    idempotency_key: None,
+                };
+                let plan = plan_index(&context, index_request)
+                    .await
+                    .map_err(|err| err.within(&["profile", &position]))?;
+                plans.push(plan);
+            }
+        }
+
+        // The indexes needed by others come first.
+        plans.sort_by_key(|plan| !plan.source.prerequisites(&plan.index_type).is_empty());
+
+        // The bundle and its indexes are created together: on the first error, dropping the
+        // transaction rolls it back.
+        let pool = &context.state.pool;
+        let mut tx = pool
+            .conn()
+            .and_then(Connection::begin)
+            .await
+            .context(error::DBError {
+                details: "could not retrieve transaction",
+            })?;
+
+        let bundle = tx
+            .create_bundle(&region, &profile)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not create bundle",
+            })?;
+        let bundle_id = bundle.bundle_id;
+
+        let mut indexes: Vec<Index> = Vec::new();
+        let mut jobs = Vec::new();
+        let mut triggered = Vec::new();
+        for plan in plans {
+            let index = match insert_plan(&mut tx, &context, &plan).await? {
+                Creation::Created { index, .. } => index,
+                // Conflicts are rejected, and there is no idempotency key to replay.
+                Creation::Existing(index_id) => {
+                    return Err(error::Error::ConflictError {
+                        details: format!("Index {} already exists", index_id),
+                    })
+                }
+            };
+            let entity = tx
+                .update_index_bundle(index.index_id, bundle_id)
+                .await
+                .context(error::DBProvideError {
+                    details: "Could not attach index to bundle",
+                })?;
+            let index = Index::from(entity);
+            let id = index.index_id;
+            let uses_osm_pbf = plan.source.uses_osm_pbf();
+
+            // Dependencies are found among the indexes of the bundle first.
+            let (fsm, dependencies) = ready_fsm(
+                &context,
+                &mut tx,
+                plan,
+                &index,
+                &indexes,
+                None,
+                &mut triggered,
+            )
+            .await?;
+
+            indexes.push(Index {
+                dependencies,
+                ..index
+            });
+            jobs.push((id, uses_osm_pbf, fsm));
+        }
+
+        tx.commit().await.context(error::DBError {
+            details: "could not commit transaction",
+        })?;
+
+        for (id, fsm) in triggered {
+            start(&context, id, fsm);
+        }
+        tokio::spawn(run_bundle(context.clone(), bundle_id, region, jobs));
+        info!(context.state.logger, "Running Region Bundle {}", bundle_id);
+
+        Ok(BundleResponseBody {
+            bundle: Bundle {
+                indexes,
+                ..Bundle::from(bundle)
+            },
+        })
+    }
+    .await
+}
+
+// Download the OSM PBF of the region once, for all the indexes of the bundle which need it,
+// and then run all the indexes. Those which depend on others wait for them.
+async fn run_bundle(
+    context: Context,
+    bundle_id: EntityId,
+    region: String,
+    jobs: Vec<(EntityId, bool, fsm::FSM)>,
+) -> Result<(), error::Error> {
+    let lease = if jobs.iter().any(|(_, uses_osm_pbf, _)| *uses_osm_pbf) {
+        update_bundle_db(&context, bundle_id, DOWNLOADING).await?;
+        let working_dir = PathBuf::from(&context.state.settings.work.working_dir);
+        let artifacts = context.state.registry.artifacts();
+        let timeout = context.state.settings.tools.timeout("osm", "download");
+        match fsm::fetch_osm_region(artifacts, working_dir, &region, timeout).await {
+            Ok(lease) => Some(lease),
+            Err(err) => {
+                update_bundle_db(&context, bundle_id, DOWNLOADING_ERROR).await?;
+                // None of the indexes is going to run.
+                let status = serde_json::to_string(&fsm::State::DownloadingError {
+                    details: format!("Could not download: {}", err),
+                })
+                .unwrap();
+                for (id, _, _) in jobs {
+                    update_db(&context, id, &status).await?;
+                }
+                return Err(err);
+            }
+        }
+    } else {
+        None
+    };
+
+    update_bundle_db(&context, bundle_id, RUNNING).await?;
+
+    // Each index holds its own lease on the PBF, which is kept until they are all done.
+    for (id, uses_osm_pbf, fsm) in jobs {
+        let fsm = match (&lease, uses_osm_pbf) {
+            (Some(lease), true) => fsm.with_lease(lease.clone()),
+            _ => fsm,
+        };
+        start(&context, id, fsm);
+    }
+
+    Ok(())
+}
+
+// Attach the indexes to the bundle, and derive the status of the bundle from theirs, once
+// they are running.
+fn with_indexes(bundle: Bundle, indexes: Vec<Index>) -> Bundle {
+    let status = if bundle.status != RUNNING {
+        bundle.status.clone()
+    } else if indexes.iter().any(Index::has_failed) {
+        String::from("Failed")
+    } else if indexes.iter().all(Index::is_available) {
+        String::from("Available")
+    } else {
+        String::from("InProgress")
+    };
+    Bundle {
+        status,
+        indexes,
+        ..bundle
+    }
+}
+
+async fn update_bundle_db(
+    context: &Context,
+    bundle_id: EntityId,
+    status: &str,
+) -> Result<Bundle, error::Error> {
+    let pool = &context.state.pool;
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    let entity =
+        tx.update_bundle_status(bundle_id, status)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not update bundle status",
+            })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    Ok(Bundle::from(entity))
+}
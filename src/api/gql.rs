@@ -1,10 +1,15 @@
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use juniper::{FieldError, FieldResult, IntoFieldError, RootNode};
 use slog::info;
 use snafu::ResultExt;
 use std::pin::Pin;
 
+use super::bundles;
 use super::indexes;
+use super::logs;
+use super::stats;
+use super::status::IndexState;
+use super::tools;
 use crate::error;
 use crate::fsm;
 use crate::state;
@@ -25,9 +30,63 @@ pub struct Query;
     Context = Context
 )]
 impl Query {
-    /// Return a list of all features
-    async fn indexes(&self, context: &Context) -> FieldResult<indexes::MultIndexesResponseBody> {
-        indexes::list_indexes(context)
+    /// Return an index
+    async fn index(&self, id: i32, context: &Context) -> FieldResult<indexes::IndexResponseBody> {
+        indexes::get_index(id, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Return the indexes matching the filter, sorted (by update date by default). With 'first',
+    /// the indexes come in pages, the next page starting after the 'endCursor' of the previous
+    /// one.
+    async fn indexes(
+        &self,
+        filter: Option<indexes::IndexFilter>,
+        order_by: Option<indexes::IndexOrder>,
+        first: Option<i32>,
+        after: Option<String>,
+        context: &Context,
+    ) -> FieldResult<indexes::MultIndexesResponseBody> {
+        indexes::list_indexes(filter, order_by, first, after, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Return how long each step of the pipeline takes, for the indexes matching the filter
+    async fn step_durations(
+        &self,
+        filter: Option<indexes::IndexFilter>,
+        context: &Context,
+    ) -> FieldResult<stats::StepDurationsResponseBody> {
+        stats::step_durations(filter, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Return the output of the tools run for an index, for one step (download, processing,
+    /// indexing, validation) or all of them, limited to the last 'tail' lines.
+    async fn index_logs(
+        &self,
+        id: i32,
+        step: Option<String>,
+        tail: Option<i32>,
+        context: &Context,
+    ) -> FieldResult<logs::IndexLogsResponseBody> {
+        logs::index_logs(id, step, tail, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Return the tools used by the data sources, with their version, or why they can't be
+    /// used
+    async fn tools(&self, context: &Context) -> FieldResult<tools::MultToolsResponseBody> {
+        Ok(tools::list_tools(context))
+    }
+
+    /// Return a list of all region bundles
+    async fn bundles(&self, context: &Context) -> FieldResult<bundles::MultBundlesResponseBody> {
+        bundles::list_bundles(context)
             .await
             .map_err(IntoFieldError::into_field_error)
     }
@@ -52,6 +111,46 @@ impl Mutation {
         info!(context.state.logger, "Done create index");
         res
     }
+
+    /// Create several indexes, all of them or none if any of the requests is refused
+    async fn create_indexes(
+        &self,
+        indexes: Vec<indexes::IndexRequestBody>,
+        context: &Context,
+    ) -> FieldResult<indexes::BatchIndexesResponseBody> {
+        info!(context.state.logger, "Calling create indexes");
+        indexes::create_indexes(indexes, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Delete an index which is not running, with its history and logs. With 'purge_data', its
+    /// elasticsearch index and downloaded datasets are deleted too, unless other indexes use
+    /// them.
+    async fn delete_index(
+        &self,
+        id: i32,
+        purge_data: Option<bool>,
+        context: &Context,
+    ) -> FieldResult<indexes::DeleteIndexResponseBody> {
+        info!(context.state.logger, "Calling delete index {}", id);
+        indexes::delete_index(id, purge_data.unwrap_or(false), context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Create all the indexes of a profile for a region
+    async fn create_region_bundle(
+        &self,
+        region: String,
+        profile: String,
+        context: &Context,
+    ) -> FieldResult<bundles::BundleResponseBody> {
+        info!(context.state.logger, "Calling create region bundle");
+        bundles::create_region_bundle(region, profile, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
 }
 
 type IndexStatusUpdateStream =
@@ -61,7 +160,15 @@ pub struct Subscription;
 
 #[juniper::graphql_subscription(Context = Context)]
 impl Subscription {
-    async fn notifications(context: &Context) -> IndexStatusUpdateStream {
+    /// Follow the state changes of indexes, optionally only those of the given indexes, index
+    /// types, regions or types of states (eg IndexingError).
+    async fn notifications(
+        ids: Option<Vec<i32>>,
+        index_types: Option<Vec<String>>,
+        regions: Option<Vec<String>>,
+        state_types: Option<Vec<String>>,
+        context: &Context,
+    ) -> IndexStatusUpdateStream {
         // Ready a subscription connection to receive notifications from the FSM
         let zmq_endpoint = format!(
             "tcp://{}:{}",
@@ -90,65 +197,93 @@ impl Subscription {
         );
 
         let logger = context.state.logger.clone();
-        let stream = zmq.map(move |msg| {
-            let msg = msg.context(error::ZMQRecvError {
-                details: String::from("ZMQ Reception Error"),
-            })?;
-            info!(logger, "Received something on GraphQL Subscription channel");
-
-            // The msg we receive is made of three parts, the topic, the id, and the serialized status.
-            // Here, we skip the topic, and extract the id.
-            let id = msg
-                .get(1) // skip the topic
-                .ok_or(error::Error::MiscError {
-                    details: String::from(
-                        "Just one item in a multipart message. That is plain wrong!",
-                    ),
-                })?
-                .as_str()
-                .ok_or(error::Error::MiscError {
-                    details: String::from("Status Message is not valid UTF8"),
-                })?
-                .parse::<i32>()
-                .context(error::ParseIntError {
-                    details: "Could not get id",
+        let stream = zmq.map(
+            move |msg| -> Result<indexes::IndexStatusUpdateBody, FieldError> {
+                let msg = msg.context(error::ZMQRecvError {
+                    details: String::from("ZMQ Reception Error"),
                 })?;
+                info!(logger, "Received something on GraphQL Subscription channel");
 
-            // The msg we receive is made of three parts, the topic, the id, and the serialized status.
-            // Here, we skip the topic, and the id, and extract the status.
-            let status = msg
-                .get(2)
-                .ok_or(error::Error::MiscError {
-                    details: String::from(
-                        "Just one item in a multipart message. That is plain wrong!",
-                    ),
-                })?
-                .as_str()
-                .ok_or(error::Error::MiscError {
-                    details: String::from("Status Message is not valid UTF8"),
-                })?;
+                // The msg we receive is made of three parts, the topic, the id, and the serialized status.
+                // Here, we skip the topic, and extract the id.
+                let id = msg
+                    .get(1) // skip the topic
+                    .ok_or(error::Error::MiscError {
+                        details: String::from(
+                            "Just one item in a multipart message. That is plain wrong!",
+                        ),
+                    })?
+                    .as_str()
+                    .ok_or(error::Error::MiscError {
+                        details: String::from("Status Message is not valid UTF8"),
+                    })?
+                    .parse::<i32>()
+                    .context(error::ParseIntError {
+                        details: "Could not get id",
+                    })?;
 
-            info!(logger, "GraphQL received status update {}", status);
+                // The msg we receive is made of three parts, the topic, the id, and the serialized status.
+                // Here, we skip the topic, and the id, and extract the status.
+                let status = msg
+                    .get(2)
+                    .ok_or(error::Error::MiscError {
+                        details: String::from(
+                            "Just one item in a multipart message. That is plain wrong!",
+                        ),
+                    })?
+                    .as_str()
+                    .ok_or(error::Error::MiscError {
+                        details: String::from("Status Message is not valid UTF8"),
+                    })?;
 
-            // The msg we have left should be a serialized version of the status.
-            if let Err(err) =
-                serde_json::from_str::<fsm::State>(status).context(error::SerdeJSONError {
-                    details: String::from("Could not deserialize state"),
-                })
-            {
-                info!(logger, "Deserialize error: {}", err);
-            }
+                info!(logger, "GraphQL received status update {}", status);
 
-            let status = String::from(status);
-            info!(logger, "string: {}", status);
+                // The msg we have left should be a serialized version of the status.
+                let state = match serde_json::from_str::<fsm::State>(status).context(
+                    error::SerdeJSONError {
+                        details: String::from("Could not deserialize state"),
+                    },
+                ) {
+                    Ok(state) => Some(IndexState::from(state)),
+                    Err(err) => {
+                        info!(logger, "Deserialize error: {}", err);
+                        None
+                    }
+                };
 
-            let resp = indexes::IndexStatusUpdateBody { id, status };
-            info!(logger, "GraphQL Notification: {:?}", resp);
-            Ok(resp)
+                let status = String::from(status);
+                info!(logger, "string: {}", status);
+
+                let resp = indexes::IndexStatusUpdateBody { id, status, state };
+                info!(logger, "GraphQL Notification: {:?}", resp);
+                Ok(resp)
+            },
+        );
+
+        let filter = indexes::NotificationFilter::new(ids, index_types, regions, state_types);
+        let context = context.clone();
+        let stream = stream.filter_map(move |resp| {
+            let filter = filter.clone();
+            let context = context.clone();
+            async move {
+                match resp {
+                    Ok(resp) => match filter.matches(&context, &resp).await {
+                        Ok(true) => Some(Ok(resp)),
+                        Ok(false) => None,
+                        Err(err) => Some(Err(err.into_field_error())),
+                    },
+                    Err(err) => Some(Err(err)),
+                }
+            }
         });
 
         Box::pin(stream)
     }
+
+    /// Follow the output of the tools run for an index, while it runs.
+    async fn index_log_stream(id: i32, context: &Context) -> logs::IndexLogStream {
+        logs::follow_index_logs(id, context)
+    }
 }
 
 type Schema = RootNode<'static, Query, Mutation, Subscription>;
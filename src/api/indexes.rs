@@ -1,15 +1,26 @@
+use async_zmq::subscribe::Subscribe;
 use async_zmq::StreamExt;
+use chrono::{DateTime, Utc};
 use futures::TryFutureExt;
-use juniper::{GraphQLInputObject, GraphQLObject};
+use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject};
 use serde::{Deserialize, Serialize};
 use slog::info;
 use snafu::ResultExt;
-use sqlx::Connection;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
 
 use crate::api::gql::Context;
 use crate::api::model::*;
-use crate::db::model::{EntityId, ProvideData};
+use crate::api::status::IndexState;
+use crate::db::model::{
+    EntityId, IndexCursor, IndexDependencyEntity, IndexEntity, IndexEventEntity, IndexQuery,
+    IndexSort, ProvideData, ProvideError,
+};
 use crate::db::Db;
 use crate::error;
 use crate::fsm;
@@ -20,6 +31,48 @@ pub struct IndexRequestBody {
     pub index_type: String,
     pub data_source: String,
     pub region: String,
+    /// A dataset already present on disk (a path or a file:// URL), inside the staging directory.
+    /// When given, the download step is skipped.
+    pub file_path: Option<String>,
+    /// The URL of the dataset, for the 'url' data source.
+    pub url: Option<String>,
+    /// The format of the dataset found at 'url' (osm-pbf, bano-csv, openaddresses-csv, ntfs-zip,
+    /// gtfs-zip, cosmogony-json)
+    pub format: Option<String>,
+    /// Tuning parameters for the indexing tools, checked against the data source.
+    pub options: Option<Vec<IndexOption>>,
+    /// What to do if an index of the same type is already running for the data source and
+    /// region. Rejected by default.
+    pub on_conflict: Option<ConflictPolicy>,
+    /// A key identifying the request, so that retrying it returns the index it created rather
+    /// than creating another one. Keys are remembered for a retention window.
+    pub idempotency_key: Option<String>,
+}
+
+/// What to do with a request for an index whose type, data source and region are those of an
+/// index already running (or waiting to run).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum)]
+pub enum ConflictPolicy {
+    /// Fail with a 'Conflict Error'
+    Reject,
+    /// Return the index already running, without creating anything
+    ReturnExisting,
+    /// Create the index, which waits for the one running to be done before starting
+    Queue,
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> Self {
+        ConflictPolicy::Reject
+    }
+}
+
+/// A tuning parameter of the indexing tools: city_level, shards, replicas, threads, dataset or
+/// config_dir, depending on the data source.
+#[derive(Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub struct IndexOption {
+    pub name: String,
+    pub value: String,
 }
 
 /// The response body for a single index
@@ -28,12 +81,224 @@ pub struct IndexResponseBody {
     index: Index,
 }
 
-/// The response body for a stream of status update The status is untyped (its just a string),
-/// because the State type is a rich enum type, which juniper does not support.
+/// What became of one of the requests of a batch
+#[derive(Debug, Serialize, GraphQLObject)]
+pub struct IndexResult {
+    /// The index created, or the one returned instead (see 'onConflict' and 'idempotencyKey')
+    index: Option<Index>,
+    /// Why the request was refused
+    error: Option<String>,
+    /// What went wrong, eg VALIDATION_ERROR or CONFLICT
+    code: Option<String>,
+    /// The fields of the request which were refused, if any
+    violations: Vec<FieldViolation>,
+}
+
+/// A field of a request which was refused, and why
+#[derive(Debug, Serialize, GraphQLObject)]
+pub struct FieldViolation {
+    /// eg UNKNOWN_DATA_SOURCE, UNKNOWN_REGION or INVALID_OPTION
+    code: String,
+    /// Where the field is in the arguments, eg ['indexes', '2', 'region']
+    path: Vec<String>,
+    message: String,
+}
+
+impl From<error::Violation> for FieldViolation {
+    fn from(violation: error::Violation) -> Self {
+        FieldViolation {
+            code: String::from(violation.code),
+            path: violation.path,
+            message: violation.message,
+        }
+    }
+}
+
+impl IndexResult {
+    fn created(index: Option<Index>) -> Self {
+        IndexResult {
+            index,
+            error: None,
+            code: None,
+            violations: Vec::new(),
+        }
+    }
+
+    fn refused(err: error::Error) -> Self {
+        let code = Some(String::from(err.code()));
+        let error = Some(err.to_string());
+        let violations = match err {
+            error::Error::ValidationError { violations, .. } => {
+                violations.into_iter().map(FieldViolation::from).collect()
+            }
+            _ => Vec::new(),
+        };
+        IndexResult {
+            index: None,
+            error,
+            code,
+            violations,
+        }
+    }
+}
+
+/// The response body for a batch of indexes. Either they were all created, or none was.
+#[derive(Debug, Serialize, GraphQLObject)]
+pub struct BatchIndexesResponseBody {
+    created: bool,
+    /// One result for each request, in order
+    results: Vec<IndexResult>,
+}
+
+impl BatchIndexesResponseBody {
+    // Nothing was created, because of the errors of some of the requests.
+    fn failed<T>(results: Vec<Result<T, error::Error>>) -> Self {
+        BatchIndexesResponseBody {
+            created: false,
+            results: results
+                .into_iter()
+                .map(|res| match res {
+                    Ok(_) => IndexResult::created(None),
+                    Err(err) => IndexResult::refused(err),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The response body for a stream of status updates
 #[derive(Debug, Serialize, GraphQLObject)]
 pub struct IndexStatusUpdateBody {
     pub id: EntityId,
+    /// The state of the index, as a JSON string. Prefer 'state'.
     pub status: String,
+    /// The state of the index.
+    pub state: Option<IndexState>,
+}
+
+// The notifications a subscriber wants: those of the given indexes, of the given types, for
+// the given regions, and with the given types of states. Empty lists don't filter anything.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationFilter {
+    ids: Vec<EntityId>,
+    index_types: Vec<String>,
+    regions: Vec<String>,
+    state_types: Vec<String>,
+    // The type and region of the indexes seen so far, which never change.
+    indexes: Arc<Mutex<HashMap<EntityId, (String, String)>>>,
+}
+
+impl NotificationFilter {
+    pub fn new(
+        ids: Option<Vec<EntityId>>,
+        index_types: Option<Vec<String>>,
+        regions: Option<Vec<String>>,
+        state_types: Option<Vec<String>>,
+    ) -> Self {
+        NotificationFilter {
+            ids: ids.unwrap_or_default(),
+            index_types: index_types.unwrap_or_default(),
+            regions: regions.unwrap_or_default(),
+            state_types: state_types.unwrap_or_default(),
+            indexes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn matches(
+        &self,
+        context: &Context,
+        update: &IndexStatusUpdateBody,
+    ) -> Result<bool, error::Error> {
+        if !self.ids.is_empty() && !self.ids.contains(&update.id) {
+            return Ok(false);
+        }
+        if !self.state_types.is_empty() {
+            match state_type(&update.status) {
+                Some(state_type) if self.state_types.contains(&state_type) => {}
+                _ => return Ok(false),
+            }
+        }
+        if self.index_types.is_empty() && self.regions.is_empty() {
+            return Ok(true);
+        }
+        let known = self.indexes.lock().unwrap().get(&update.id).cloned();
+        let (index_type, region) = match known {
+            Some(index) => index,
+            None => {
+                let entity = index_entity_db(context, update.id).await?;
+                let index = (entity.index_type, entity.region);
+                self.indexes
+                    .lock()
+                    .unwrap()
+                    .insert(update.id, index.clone());
+                index
+            }
+        };
+        Ok(
+            (self.index_types.is_empty() || self.index_types.contains(&index_type))
+                && (self.regions.is_empty() || self.regions.contains(&region)),
+        )
+    }
+}
+
+// The type of a serialized state, eg 'Available'.
+fn state_type(status: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(status)
+        .ok()?
+        .get("type")?
+        .as_str()
+        .map(String::from)
+}
+
+/// How to filter indexes. The filters which are given must all match.
+#[derive(Debug, Default, Serialize, Deserialize, GraphQLInputObject)]
+pub struct IndexFilter {
+    pub index_type: Option<String>,
+    pub data_source: Option<String>,
+    pub region: Option<String>,
+    /// The type of the state of the index, eg Available or IndexingError
+    pub state_type: Option<String>,
+    /// Created at or after this date
+    pub created_after: Option<DateTime<Utc>>,
+    /// Created at or before this date
+    pub created_before: Option<DateTime<Utc>>,
+    /// Updated at or after this date
+    pub updated_after: Option<DateTime<Utc>>,
+    /// Updated at or before this date
+    pub updated_before: Option<DateTime<Utc>>,
+}
+
+impl IndexFilter {
+    // The query for the indexes matching the filter, in the default order.
+    pub(in crate::api) fn query(self) -> IndexQuery {
+        IndexQuery {
+            index_type: self.index_type,
+            data_source: self.data_source,
+            region: self.region,
+            state_type: self.state_type,
+            created_after: self.created_after,
+            created_before: self.created_before,
+            updated_after: self.updated_after,
+            updated_before: self.updated_before,
+            ..IndexQuery::default()
+        }
+    }
+}
+
+/// The field indexes are sorted on. Indexes with the same value are sorted by id.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, GraphQLEnum)]
+pub enum IndexOrderField {
+    IndexId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+/// How to sort indexes
+#[derive(Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub struct IndexOrder {
+    pub field: IndexOrderField,
+    /// Ascending by default
+    pub descending: Option<bool>,
 }
 
 /// The response body for multiple indexes
@@ -41,98 +306,1271 @@ pub struct IndexStatusUpdateBody {
 #[serde(rename_all = "camelCase")]
 pub struct MultIndexesResponseBody {
     indexes: Vec<Index>,
+    /// The number of indexes in this page
     indexes_count: i32,
+    /// The number of indexes matching the filter, in all pages
+    total_count: i32,
+    /// The cursor of the last index of this page, to get the next one
+    end_cursor: Option<String>,
+    has_next_page: bool,
+}
+
+/// Retrieve a single index
+pub async fn get_index(
+    index_id: EntityId,
+    context: &Context,
+) -> Result<IndexResponseBody, error::Error> {
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    let entity = tx
+        .get_index(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: format!("Could not get index {}", index_id),
+        })?;
+
+    let dependencies = tx
+        .get_index_dependencies(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index dependencies",
+        })?
+        .into_iter()
+        .map(|dependency| dependency.index_id)
+        .collect();
+
+    let history = tx
+        .get_index_events(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index history",
+        })?
+        .into_iter()
+        .map(IndexEvent::from)
+        .collect();
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    Ok(IndexResponseBody {
+        index: Index {
+            dependencies,
+            history,
+            ..Index::from(entity)
+        },
+    })
+}
+
+/// Retrieve the indexes matching the filter, sorted, 'first' at a time, after the index with
+/// the given cursor.
+pub async fn list_indexes(
+    filter: Option<IndexFilter>,
+    order_by: Option<IndexOrder>,
+    first: Option<i32>,
+    after: Option<String>,
+    context: &Context,
+) -> Result<MultIndexesResponseBody, error::Error> {
+    let filter = filter.unwrap_or_default();
+    let (sort, descending) = match order_by {
+        Some(order) => {
+            let sort = match order.field {
+                IndexOrderField::IndexId => IndexSort::IndexId,
+                IndexOrderField::CreatedAt => IndexSort::CreatedAt,
+                IndexOrderField::UpdatedAt => IndexSort::UpdatedAt,
+            };
+            (sort, order.descending.unwrap_or(false))
+        }
+        None => (IndexSort::default(), false),
+    };
+    let first = first
+        .map(|first| match u32::try_from(first) {
+            Ok(first) if first > 0 => Ok(first),
+            _ => Err(error::Error::MiscError {
+                details: format!("Invalid first {}, expected a positive number", first),
+            }),
+        })
+        .transpose()?;
+    let after = after.as_deref().map(parse_cursor).transpose()?;
+
+    let query = IndexQuery {
+        sort,
+        descending,
+        after,
+        // One more, to tell whether there is a next page.
+        limit: first.map(|first| first + 1),
+        ..filter.query()
+    };
+
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve indexes",
+        })?;
+
+    let mut entities = tx
+        .find_indexes(&query)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not find indexes",
+        })?;
+
+    let total_count = tx
+        .count_indexes(&query)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not count indexes",
+        })?;
+
+    let has_next_page = match first {
+        Some(first) if entities.len() > first as usize => {
+            entities.truncate(first as usize);
+            true
+        }
+        _ => false,
+    };
+
+    // Only the relations of the indexes of the page.
+    let index_ids = entities
+        .iter()
+        .map(|entity| entity.index_id)
+        .collect::<Vec<_>>();
+
+    let edges = tx
+        .get_index_dependencies_of(&index_ids)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index dependencies",
+        })?;
+
+    let events = tx
+        .get_index_events_of(&index_ids)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index history",
+        })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not retrieve indexes",
+    })?;
+    let end_cursor = entities
+        .last()
+        .map(|entity| format_cursor(IndexCursor::new(entity, sort)));
+    let indexes = with_relations(entities, &edges, events);
+
+    Ok(MultIndexesResponseBody {
+        indexes_count: i32::try_from(indexes.len()).unwrap(),
+        indexes,
+        total_count: i32::try_from(total_count).unwrap_or(i32::MAX),
+        end_cursor,
+        has_next_page,
+    })
+}
+
+// Cursors are opaque to clients, and only valid for the order they were given with.
+fn format_cursor(cursor: IndexCursor) -> String {
+    format!("{}:{}", cursor.value, cursor.index_id)
+}
+
+fn parse_cursor(cursor: &str) -> Result<IndexCursor, error::Error> {
+    let mut parts = cursor.splitn(2, ':');
+    match (
+        parts.next().and_then(|value| value.parse::<i64>().ok()),
+        parts.next().and_then(|id| id.parse::<EntityId>().ok()),
+    ) {
+        (Some(value), Some(index_id)) => Ok(IndexCursor { value, index_id }),
+        _ => Err(error::Error::MiscError {
+            details: format!("Invalid cursor '{}'", cursor),
+        }),
+    }
+}
+
+// A single index, without its dependencies.
+async fn index_entity_db(
+    context: &Context,
+    index_id: EntityId,
+) -> Result<IndexEntity, error::Error> {
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    let entity = tx
+        .get_index(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: format!("Could not get index {}", index_id),
+        })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    Ok(entity)
+}
+
+// All the indexes, along with their dependencies.
+pub(in crate::api) async fn all_indexes_db(context: &Context) -> Result<Vec<Index>, error::Error> {
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve indexes",
+        })?;
+
+    let entities = tx.get_all_indexes().await.context(error::DBProvideError {
+        details: "Could not get all them indexes",
+    })?;
+
+    let edges = tx
+        .get_all_index_dependencies()
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index dependencies",
+        })?;
+
+    let events = tx
+        .get_all_index_events()
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index history",
+        })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not retrieve indexes",
+    })?;
+
+    Ok(with_relations(entities, &edges, events))
+}
+
+// The indexes, each with the indexes it depends on, and the states it went through.
+fn with_relations(
+    entities: Vec<IndexEntity>,
+    edges: &[IndexDependencyEntity],
+    events: Vec<IndexEventEntity>,
+) -> Vec<Index> {
+    let mut history = HashMap::<EntityId, Vec<IndexEvent>>::new();
+    for event in events {
+        history
+            .entry(event.index_id)
+            .or_default()
+            .push(IndexEvent::from(event));
+    }
+    entities
+        .into_iter()
+        .map(|entity| {
+            let dependencies = edges
+                .iter()
+                .filter(|edge| edge.index_id == entity.index_id)
+                .map(|edge| edge.dependency_id)
+                .collect();
+            Index {
+                dependencies,
+                history: history.remove(&entity.index_id).unwrap_or_default(),
+                ..Index::from(entity)
+            }
+        })
+        .collect()
+}
+
+/// What was removed along with an index
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteIndexResponseBody {
+    pub index_id: EntityId,
+    /// How many states of the history of the index were removed
+    pub history_count: i32,
+    /// The elasticsearch index removed, if any
+    pub es_index: Option<String>,
+    /// The elasticsearch alias removed along with the index, if it pointed to it
+    pub es_alias: Option<String>,
+    /// The files and directories removed from the working directory, including the logs
+    pub files: Vec<String>,
+    /// What was kept, and why
+    pub warnings: Vec<String>,
+}
+
+/// Delete an index, which must not be running, with its history and its logs. With
+/// 'purge_data', its elasticsearch index and the datasets it downloaded are removed too, unless
+/// other indexes use them.
+pub async fn delete_index(
+    index_id: EntityId,
+    purge_data: bool,
+    context: &Context,
+) -> Result<DeleteIndexResponseBody, error::Error> {
+    let logs = &context.state.logs;
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    // The checks are made in the transaction of the delete, so that the job of the index, or of
+    // an index depending on it, can't start in between.
+    let entity = tx
+        .get_index(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: format!("Could not get index {}", index_id),
+        })?;
+    if entity.active {
+        return Err(error::Error::MiscError {
+            details: format!(
+                "Index {} is running, it can't be deleted before it is done",
+                index_id
+            ),
+        });
+    }
+
+    // The indexes waiting for this one would wait forever.
+    let waiting = tx
+        .get_index_dependents(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get the indexes depending on the index",
+        })?
+        .into_iter()
+        .filter(|dependent| dependent.active)
+        .map(|dependent| dependent.index_id.to_string())
+        .collect::<Vec<_>>();
+    if !waiting.is_empty() {
+        return Err(error::Error::MiscError {
+            details: format!(
+                "Index {} is needed by running indexes {}, it can't be deleted before they are done",
+                index_id,
+                waiting.join(", ")
+            ),
+        });
+    }
+
+    let history = tx
+        .get_index_events(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index history",
+        })?;
+
+    let entity = tx
+        .delete_index(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: format!("Could not delete index {}", index_id),
+        })?;
+
+    let others = tx.get_all_indexes().await.context(error::DBProvideError {
+        details: "Could not get all them indexes",
+    })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    info!(context.state.logger, "Deleted index {}", index_id);
+
+    let mut report = DeleteIndexResponseBody {
+        index_id,
+        history_count: i32::try_from(history.len()).unwrap(),
+        es_index: None,
+        es_alias: None,
+        files: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    match logs.remove(index_id) {
+        Ok(Some(dir)) => report.files.push(dir.display().to_string()),
+        Ok(None) => {}
+        Err(err) => report.warnings.push(err.to_string()),
+    }
+
+    if purge_data {
+        purge_elasticsearch(context, &entity, &others, &mut report).await;
+        purge_downloads(context, &entity, &others, &mut report);
+    }
+
+    Ok(report)
+}
+
+// Remove the elasticsearch index holding the data of the index, unless another index uses it.
+async fn purge_elasticsearch(
+    context: &Context,
+    entity: &IndexEntity,
+    others: &[IndexEntity],
+    report: &mut DeleteIndexResponseBody,
+) {
+    let es_index = match &entity.es_index {
+        Some(es_index) => es_index,
+        None => return,
+    };
+    if let Some(other) = others
+        .iter()
+        .find(|other| other.es_index.as_ref() == Some(es_index))
+    {
+        report.warnings.push(format!(
+            "Kept elasticsearch index {}, used by index {}",
+            es_index, other.index_id
+        ));
+        return;
+    }
+    let settings = &context.state.settings.elasticsearch;
+    let endpoint = format!("http://{}:{}", settings.host, settings.port);
+    let es = match Url::parse(&endpoint) {
+        Ok(es) => es,
+        Err(err) => {
+            report.warnings.push(format!(
+                "Could not parse elasticsearch URL '{}': {}",
+                endpoint, err
+            ));
+            return;
+        }
+    };
+    // The alias goes along with the index it points to.
+    let es_alias = match &entity.es_alias {
+        Some(alias) => match fsm::resolve_alias(es.clone(), alias).await {
+            Ok(index) if index == *es_index => Some(alias),
+            _ => None,
+        },
+        None => None,
+    };
+    match fsm::delete_es_index(es, es_index).await {
+        Ok(()) => {
+            report.es_index = Some(es_index.clone());
+            report.es_alias = es_alias.cloned();
+        }
+        Err(err) => report.warnings.push(err.to_string()),
+    }
+}
+
+// Remove the datasets downloaded for the index in the working directory, unless another index
+// used them, or a job is using them. Datasets staged on disk are never removed.
+fn purge_downloads(
+    context: &Context,
+    entity: &IndexEntity,
+    others: &[IndexEntity],
+    report: &mut DeleteIndexResponseBody,
+) {
+    let provenance = entity
+        .provenance
+        .as_ref()
+        .and_then(|provenance| serde_json::from_str::<fsm::Provenance>(provenance).ok());
+    let inputs = match provenance {
+        Some(provenance) => provenance.inputs,
+        None => return,
+    };
+    let used = others
+        .iter()
+        .filter_map(|other| other.provenance.as_ref())
+        .filter_map(|provenance| serde_json::from_str::<fsm::Provenance>(provenance).ok())
+        .flat_map(|provenance| provenance.inputs)
+        .map(|input| input.file)
+        .collect::<Vec<_>>();
+    let working_dir = PathBuf::from(&context.state.settings.work.working_dir);
+    let artifacts = context.state.registry.artifacts();
+    for input in inputs {
+        let file = &input.file;
+        if input.url.is_none() || !file.starts_with(&working_dir) {
+            continue;
+        }
+        if !file.exists() {
+            report
+                .warnings
+                .push(format!("{} was already removed", file.display()));
+        } else if used.contains(file) || artifacts.in_use(file) {
+            report
+                .warnings
+                .push(format!("Kept {}, used by other indexes", file.display()));
+        } else {
+            match fsm::remove_download(file) {
+                Ok(()) => report.files.push(file.display().to_string()),
+                Err(err) => report.warnings.push(err.to_string()),
+            }
+        }
+    }
+}
+
+/// Create a new index
+pub async fn create_index(
+    index_request: IndexRequestBody,
+    context: &Context,
+) -> Result<IndexResponseBody, error::Error> {
+    async move {
+        info!(
+            context.state.logger,
+            "Creating Index {} {} {}",
+            index_request.index_type,
+            index_request.data_source,
+            index_request.region
+        );
+
+        // We check the request before creating anything in the database.
+        let plan = plan_index(&context, index_request)
+            .await
+            .map_err(|err| err.within(&["index"]))?;
+
+        let pool = &context.state.pool;
+        let mut tx = pool
+            .conn()
+            .and_then(Connection::begin)
+            .await
+            .context(error::DBError {
+                details: "could not retrieve transaction",
+            })?;
+
+        let creation = match insert_plan(&mut tx, &context, &plan).await {
+            Err(
+                err @ error::Error::DBProvideError {
+                    source: ProvideError::UniqueViolation { .. },
+                    ..
+                },
+            ) => {
+                drop(tx);
+                // The same request may have come in the meantime.
+                if let Some((key, request)) = plan.idempotency() {
+                    if let Some(response) = replay_index(&context, key, request).await? {
+                        return Ok(response);
+                    }
+                }
+                return Err(err);
+            }
+            res => res?,
+        };
+
+        let (index, queued_behind) = match creation {
+            Creation::Created {
+                index,
+                queued_behind,
+            } => (index, queued_behind),
+            Creation::Existing(index_id) => {
+                tx.commit().await.context(error::DBError {
+                    details: "could not commit transaction",
+                })?;
+                return get_index(index_id, &context).await;
+            }
+        };
+        let id = index.index_id;
+
+        // Should the FSM not be ready, dropping the transaction rolls the index back.
+        let mut jobs = Vec::new();
+        let (fsm, dependencies) = ready_fsm(
+            &context,
+            &mut tx,
+            plan,
+            &index,
+            &[],
+            queued_behind,
+            &mut jobs,
+        )
+        .await?;
+
+        tx.commit().await.context(error::DBError {
+            details: "could not commit transaction",
+        })?;
+
+        jobs.push((id, fsm));
+        for (id, fsm) in jobs {
+            start(&context, id, fsm);
+        }
+
+        Ok(IndexResponseBody {
+            index: Index {
+                dependencies,
+                ..index
+            },
+        })
+    }
+    .await
+}
+
+/// Create several indexes at once, all of them or none. The requests are all checked first,
+/// and the indexes are created in a single transaction.
+pub async fn create_indexes(
+    index_requests: Vec<IndexRequestBody>,
+    context: &Context,
+) -> Result<BatchIndexesResponseBody, error::Error> {
+    async move {
+        info!(
+            context.state.logger,
+            "Creating {} Indexes",
+            index_requests.len()
+        );
+
+        let mut keys = HashSet::new();
+        let mut plans = Vec::new();
+        for (position, index_request) in index_requests.into_iter().enumerate() {
+            let position = position.to_string();
+            let plan = plan_index(&context, index_request).await.and_then(|plan| {
+                if let Some(key) = &plan.idempotency_key {
+                    if !keys.insert(key.clone()) {
+                        return Err(error::Error::validation(vec![error::Violation {
+                            code: DUPLICATE_IDEMPOTENCY_KEY,
+                            path: vec![String::from("idempotencyKey")],
+                            message: format!("Idempotency key '{}' is used more than once", key),
+                        }]));
+                    }
+                }
+                Ok(plan)
+            });
+            plans.push(plan.map_err(|err| err.within(&["indexes", &position])));
+        }
+        if plans.iter().any(Result::is_err) {
+            return Ok(BatchIndexesResponseBody::failed(plans));
+        }
+        let plans = plans.into_iter().filter_map(Result::ok).collect::<Vec<_>>();
+
+        let pool = &context.state.pool;
+        let mut tx = pool
+            .conn()
+            .and_then(Connection::begin)
+            .await
+            .context(error::DBError {
+                details: "could not retrieve transaction",
+            })?;
+
+        let mut creations = Vec::new();
+        for plan in plans.iter() {
+            creations.push(insert_plan(&mut tx, &context, plan).await);
+        }
+        if creations.iter().any(Result::is_err) {
+            // Dropping the transaction rolls it back.
+            return Ok(BatchIndexesResponseBody::failed(creations));
+        }
+
+        let mut results = Vec::new();
+        let mut existing = Vec::new();
+        let mut created = Vec::new();
+        for (position, (plan, creation)) in plans.into_iter().zip(creations).enumerate() {
+            results.push(None);
+            match creation? {
+                Creation::Created {
+                    index,
+                    queued_behind,
+                } => created.push((position, plan, index, queued_behind)),
+                Creation::Existing(index_id) => existing.push((position, index_id)),
+            }
+        }
+
+        // The indexes needed by others come first, and dependencies are found among the
+        // indexes of the batch for the same region first.
+        created.sort_by_key(|(_, plan, _, _)| {
+            (
+                plan.region.clone(),
+                !plan.source.prerequisites(&plan.index_type).is_empty(),
+            )
+        });
+        let mut indexes: Vec<Index> = Vec::new();
+        let mut positions = Vec::new();
+        let mut jobs = Vec::new();
+        let mut triggered = Vec::new();
+        let mut region_start = 0;
+        for (position, plan, index, queued_behind) in created {
+            if indexes
+                .get(region_start)
+                .map(|sibling| sibling.region != index.region)
+                .unwrap_or(false)
+            {
+                region_start = indexes.len();
+            }
+            let id = index.index_id;
+            // Should an FSM not be ready, dropping the transaction rolls the whole batch back.
+            let (fsm, dependencies) = ready_fsm(
+                &context,
+                &mut tx,
+                plan,
+                &index,
+                &indexes[region_start..],
+                queued_behind,
+                &mut triggered,
+            )
+            .await?;
+            indexes.push(Index {
+                dependencies,
+                ..index
+            });
+            positions.push(position);
+            jobs.push((id, fsm));
+        }
+
+        tx.commit().await.context(error::DBError {
+            details: "could not commit transaction",
+        })?;
+
+        for (id, fsm) in triggered.into_iter().chain(jobs) {
+            start(&context, id, fsm);
+        }
+
+        for (position, index) in positions.into_iter().zip(indexes) {
+            results[position] = Some(index);
+        }
+        for (position, index_id) in existing {
+            results[position] = Some(get_index(index_id, &context).await?.index);
+        }
+
+        Ok(BatchIndexesResponseBody {
+            created: true,
+            results: results.into_iter().map(IndexResult::created).collect(),
+        })
+    }
+    .await
+}
+
+// A request for an index, checked against the data sources and the staging directory.
+pub(in crate::api) struct IndexPlan {
+    pub(in crate::api) index_type: String,
+    data_source: String,
+    region: String,
+    pub(in crate::api) source: Arc<dyn fsm::DataSource>,
+    file_path: Option<PathBuf>,
+    remote: Option<(Url, fsm::Format)>,
+    options: fsm::Options,
+    on_conflict: ConflictPolicy,
+    idempotency_key: Option<String>,
+    request: String, // As JSON, to tell a retry from another request with the same key
+}
+
+impl IndexPlan {
+    // The key of the request, and the request, if the client gave one.
+    fn idempotency(&self) -> Option<(&str, &str)> {
+        self.idempotency_key
+            .as_deref()
+            .map(|key| (key, self.request.as_str()))
+    }
+}
+
+// The codes of the violations found in requests for indexes.
+const MISSING_FIELD: &str = "MISSING_FIELD";
+const UNEXPECTED_FIELD: &str = "UNEXPECTED_FIELD";
+const UNKNOWN_DATA_SOURCE: &str = "UNKNOWN_DATA_SOURCE";
+const DATA_SOURCE_UNAVAILABLE: &str = "DATA_SOURCE_UNAVAILABLE";
+const UNSUPPORTED_INDEX_TYPE: &str = "UNSUPPORTED_INDEX_TYPE";
+pub(in crate::api) const UNKNOWN_REGION: &str = "UNKNOWN_REGION";
+const INVALID_FILE_PATH: &str = "INVALID_FILE_PATH";
+const INVALID_URL: &str = "INVALID_URL";
+const INVALID_FORMAT: &str = "INVALID_FORMAT";
+const UNSUPPORTED_OPTION: &str = "UNSUPPORTED_OPTION";
+const INVALID_OPTION: &str = "INVALID_OPTION";
+const DUPLICATE_IDEMPOTENCY_KEY: &str = "DUPLICATE_IDEMPOTENCY_KEY";
+
+// What is wrong with a request, the paths of the fields being relative to the request.
+#[derive(Default)]
+struct Violations(Vec<error::Violation>);
+
+impl Violations {
+    fn add<S: Into<String>>(&mut self, code: &'static str, path: &[&str], message: S) {
+        self.0.push(error::Violation {
+            code,
+            path: path.iter().map(|segment| String::from(*segment)).collect(),
+            message: message.into(),
+        });
+    }
+
+    // The value checked, or None once its violation is recorded.
+    fn check<T>(
+        &mut self,
+        code: &'static str,
+        path: &[&str],
+        res: Result<T, error::Error>,
+    ) -> Option<T> {
+        match res {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.add(code, path, err.to_string());
+                None
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// Check, before anything is created, that the data source can produce this type of index and
+// has a dataset for the region, and check the staged dataset, the remote dataset and the
+// options. All that is wrong with the request is reported at once.
+pub(in crate::api) async fn plan_index(
+    context: &Context,
+    index_request: IndexRequestBody,
+) -> Result<IndexPlan, error::Error> {
+    let request = serde_json::to_string(&index_request).context(error::SerdeJSONError {
+        details: String::from("Could not serialize index request"),
+    })?;
+
+    let IndexRequestBody {
+        index_type,
+        data_source,
+        region,
+        file_path,
+        url,
+        format,
+        options,
+        on_conflict,
+        idempotency_key,
+    } = index_request;
+
+    let registry = &context.state.registry;
+    let mut violations = Violations::default();
+
+    let (code, path) = match registry.get(&data_source) {
+        None => (UNKNOWN_DATA_SOURCE, "dataSource"),
+        Some(source) if !source.index_types().contains(&index_type.as_str()) => {
+            (UNSUPPORTED_INDEX_TYPE, "indexType")
+        }
+        Some(_) => (DATA_SOURCE_UNAVAILABLE, "dataSource"),
+    };
+    let source = violations.check(code, &[path], registry.find(&data_source, &index_type));
+
+    let file_path = violations.check(
+        INVALID_FILE_PATH,
+        &["filePath"],
+        file_path
+            .map(|path| fsm::resolve_staged_path(&context.state.settings.work.staging_dir, &path))
+            .transpose(),
+    );
+
+    let remote = remote_dataset(&index_type, &data_source, url, format, &mut violations);
+
+    let options = options.unwrap_or_default();
+    let config_root = &context.state.settings.work.config_root;
+    let options = match &source {
+        Some(source) => {
+            let before = violations.len();
+            for (position, option) in options.iter().enumerate() {
+                let position = position.to_string();
+                let (code, field) = if source.options().contains(&option.name.as_str()) {
+                    (INVALID_OPTION, "value")
+                } else {
+                    (UNSUPPORTED_OPTION, "name")
+                };
+                let pair = (option.name.clone(), option.value.clone());
+                violations.check(
+                    code,
+                    &["options", &position, field],
+                    fsm::Options::parse(source.as_ref(), vec![pair], config_root),
+                );
+            }
+            if violations.len() == before {
+                let pairs = options
+                    .into_iter()
+                    .map(|option| (option.name, option.value))
+                    .collect();
+                violations.check(
+                    INVALID_OPTION,
+                    &["options"],
+                    fsm::Options::parse(source.as_ref(), pairs, config_root),
+                )
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    if region.trim().is_empty() {
+        violations.add(MISSING_FIELD, &["region"], "The region can't be empty");
+    } else if let (Some(source), Some(None), None) = (&source, &file_path, &remote) {
+        // Unless the dataset is given, the data source must have one for the region.
+        violations.check(
+            UNKNOWN_REGION,
+            &["region"],
+            source.check_region(&region).await,
+        );
+    }
+
+    let (source, file_path, options) = match (source, file_path, options) {
+        (Some(source), Some(file_path), Some(options)) if violations.is_empty() => {
+            (source, file_path, options)
+        }
+        _ => return Err(error::Error::validation(violations.0)),
+    };
+    let options = with_dataset(context, &region, options);
+
+    Ok(IndexPlan {
+        index_type,
+        data_source,
+        region,
+        source,
+        file_path,
+        remote,
+        options,
+        on_conflict: on_conflict.unwrap_or_default(),
+        idempotency_key,
+        request,
+    })
+}
+
+// The FSM of a newly created index, which waits for its dependencies (found among the siblings
+// first), and for the index it is queued behind, if any. It is made in the transaction of the
+// index, so that nothing is left behind if it can't be (see prepare_dependencies).
+pub(in crate::api) async fn ready_fsm(
+    context: &Context,
+    conn: &mut SqliteConnection,
+    plan: IndexPlan,
+    index: &Index,
+    siblings: &[Index],
+    queued_behind: Option<EntityId>,
+    triggered: &mut Vec<(EntityId, fsm::FSM)>,
+) -> Result<(fsm::FSM, Vec<EntityId>), error::Error> {
+    let IndexPlan {
+        source,
+        file_path,
+        remote,
+        options,
+        ..
+    } = plan;
+    let id = index.index_id;
+
+    let fsm = fsm::FSM::new(
+        id,
+        index.index_type.as_str(),
+        source.clone(),
+        index.region.as_str(),
+        &context.state.settings,
+        context.state.publisher.clone(),
+        context.state.logger.clone(),
+    )?;
+
+    let fsm = fsm.with_options(options).with_logs(&context.state.logs);
+
+    let fsm = match file_path {
+        Some(file_path) => fsm.with_staged_path(file_path),
+        None => fsm,
+    };
+
+    let fsm = match remote {
+        Some((url, format)) => fsm.with_remote(url, format),
+        None => fsm,
+    };
+
+    let (fsm, dependencies) = prepare_dependencies(
+        context,
+        conn,
+        fsm,
+        index,
+        source.as_ref(),
+        siblings,
+        triggered,
+    )
+    .await?;
+
+    let fsm = match queued_behind {
+        Some(existing_id) => {
+            let turn = wait_for_turn(context.clone(), id);
+            fsm.with_queue(existing_id, Box::pin(turn))
+        }
+        None => fsm,
+    };
+
+    Ok((fsm, dependencies))
 }
 
-impl From<Vec<Index>> for MultIndexesResponseBody {
-    fn from(indexes: Vec<Index>) -> Self {
-        let indexes_count = i32::try_from(indexes.len()).unwrap();
-        Self {
-            indexes,
-            indexes_count,
-        }
+// Unless one is given, the dataset is derived from the region, so that the indexes of several
+// regions can coexist in elasticsearch.
+fn with_dataset(context: &Context, region: &str, options: fsm::Options) -> fsm::Options {
+    let prefix = &context.state.settings.elasticsearch.dataset_prefix;
+    fsm::Options {
+        dataset: options
+            .dataset
+            .or_else(|| Some(fsm::dataset_name(prefix, region))),
+        ..options
     }
 }
 
-/// Retrieve all indexes
-pub async fn list_indexes(context: &Context) -> Result<MultIndexesResponseBody, error::Error> {
-    async move {
-        let pool = &context.state.pool;
-
-        let mut tx = pool
-            .conn()
-            .and_then(Connection::begin)
-            .await
-            .context(error::DBError {
-                details: "could not retrieve indexes",
-            })?;
-
-        let entities = tx.get_all_indexes().await.context(error::DBProvideError {
-            details: "Could not get all them indexes",
-        })?;
-
-        let indexes = entities.into_iter().map(Index::from).collect::<Vec<_>>();
+// Run the FSM of an index, and keep the database up to date with its progress.
+pub(in crate::api) fn start(context: &Context, index_id: EntityId, fsm: fsm::FSM) {
+    // Listen to FSM for updates
+    let ct2 = context.clone();
+    tokio::spawn(update_notifications(ct2, index_id));
+    info!(context.state.logger, "Listening to state changes");
 
-        tx.commit().await.context(error::DBError {
-            details: "could not retrieve indexes",
-        })?;
+    tokio::spawn(fsm::exec(fsm));
+    info!(context.state.logger, "Running FSM");
+}
 
-        Ok(MultIndexesResponseBody::from(indexes))
+// Some indexes need other indexes for the same region (eg streets need admins), in which case
+// the FSM waits for them before starting. The dependencies are recorded in the transaction of
+// the index, and the indexes created for them are added to 'triggered', to be started once it
+// is committed.
+async fn prepare_dependencies(
+    context: &Context,
+    conn: &mut SqliteConnection,
+    fsm: fsm::FSM,
+    index: &Index,
+    source: &dyn fsm::DataSource,
+    siblings: &[Index],
+    triggered: &mut Vec<(EntityId, fsm::FSM)>,
+) -> Result<(fsm::FSM, Vec<EntityId>), error::Error> {
+    let prerequisites = source.prerequisites(&index.index_type);
+    if prerequisites.is_empty() {
+        return Ok((fsm, Vec::new()));
     }
-    .await
+    let region = match source.prerequisites_region(&index.region) {
+        Some(region) => region,
+        None => {
+            info!(
+                context.state.logger,
+                "No region to wait for {:?} for index {}", prerequisites, index.index_id
+            );
+            return Ok((fsm, Vec::new()));
+        }
+    };
+    // We listen to notifications before creating any dependency, so that we don't miss
+    // its failure.
+    let zmq = subscribe(context)?;
+    let dependencies = resolve_dependencies(
+        context,
+        conn,
+        index.index_id,
+        &region,
+        prerequisites,
+        siblings,
+        triggered,
+    )
+    .await?;
+    let wait = wait_for_dependencies(context.clone(), index.index_id, dependencies.clone(), zmq);
+    Ok((
+        fsm.with_dependencies(dependencies.clone(), Box::pin(wait)),
+        dependencies,
+    ))
 }
 
-/// Create a new index
-pub async fn create_index(
-    index_request: IndexRequestBody,
+// Find, for each of the prerequisites, an index of the region we can depend on: one of the
+// siblings created along with the index, a fresh available index, or one an active job is
+// working on. If there is none, we create one.
+async fn resolve_dependencies(
     context: &Context,
-) -> Result<IndexResponseBody, error::Error> {
-    async move {
-        let IndexRequestBody {
-            index_type,
-            data_source,
-            region,
-        } = index_request;
-
+    conn: &mut SqliteConnection,
+    index_id: EntityId,
+    region: &str,
+    prerequisites: &[&str],
+    siblings: &[Index],
+    triggered: &mut Vec<(EntityId, fsm::FSM)>,
+) -> Result<Vec<EntityId>, error::Error> {
+    let max_age = chrono::Duration::seconds(context.state.settings.dependencies.max_age as i64);
+    let mut dependencies = Vec::new();
+    for index_type in prerequisites {
+        if let Some(sibling) = siblings
+            .iter()
+            .find(|index| index.index_type == *index_type && index.region == region)
+        {
+            create_dependency(conn, index_id, sibling.index_id).await?;
+            dependencies.push(sibling.index_id);
+            continue;
+        }
+        let latest =
+            conn.get_latest_index(index_type, region)
+                .await
+                .context(error::DBProvideError {
+                    details: "Could not get latest index",
+                })?;
+        let usable = latest.filter(|index| {
+            match serde_json::from_str::<fsm::State>(&index.status) {
+                Ok(fsm::State::Available) => Utc::now() - index.updated_at < max_age,
+                // Only a running job gets anywhere: an index which is not active either failed,
+                // waits for its turn, or was left over by a previous run.
+                Ok(status) => index.active && !status.is_error(),
+                Err(_) => false,
+            }
+        });
+        let dependency_id = match usable {
+            Some(index) => index.index_id,
+            None => trigger_index(context, conn, index_type, region, triggered).await?,
+        };
         info!(
             context.state.logger,
-            "Creating Index {} {} {}", index_type, data_source, region
+            "Index {} depends on {} index {}", index_id, index_type, dependency_id
         );
+        create_dependency(conn, index_id, dependency_id).await?;
+        dependencies.push(dependency_id);
+    }
+    Ok(dependencies)
+}
 
-        let index = create_db(&context, &index_type, &data_source, &region).await?;
-        let id = index.index_id;
+// Create an index needed by another one, using the data source configured for its type, and
+// add its FSM to 'triggered'. Its own prerequisites are not considered, which is fine as long
+// as only admins are triggered.
+async fn trigger_index(
+    context: &Context,
+    conn: &mut SqliteConnection,
+    index_type: &str,
+    region: &str,
+    triggered: &mut Vec<(EntityId, fsm::FSM)>,
+) -> Result<EntityId, error::Error> {
+    let data_source = context
+        .state
+        .settings
+        .dependencies
+        .sources
+        .get(index_type)
+        .ok_or(error::Error::MiscError {
+            details: format!("No data source configured to create {} indexes", index_type),
+        })?;
+    let source = context.state.registry.find(data_source, index_type)?;
+
+    info!(
+        context.state.logger,
+        "Creating Index {} {} {} as a dependency", index_type, data_source, region
+    );
+
+    let options = with_dataset(context, region, fsm::Options::default());
+    let res = insert_index(conn, index_type, data_source, region, &options, true, None).await;
+    let index = match res {
+        Ok(index) => index,
+        Err(
+            err @ error::Error::DBProvideError {
+                source: ProvideError::UniqueViolation { .. },
+                ..
+            },
+        ) => {
+            // One is already running for the region (or was just triggered by another index
+            // of the transaction), we depend on it instead.
+            let active = conn
+                .get_active_index(index_type, data_source, region)
+                .await
+                .context(error::DBProvideError {
+                    details: "Could not get active index",
+                })?;
+            return match active {
+                Some(index) => Ok(index.index_id),
+                None => Err(err),
+            };
+        }
+        Err(err) => return Err(err),
+    };
+    let id = index.index_id;
+
+    let fsm = fsm::FSM::new(
+        id,
+        index_type,
+        source,
+        region,
+        &context.state.settings,
+        context.state.publisher.clone(),
+        context.state.logger.clone(),
+    )?
+    .with_options(options)
+    .with_logs(&context.state.logs);
 
-        let fsm = fsm::FSM::new(
-            id,
-            index_type,
-            data_source,
-            region,
-            &context.state.settings,
-            String::from("state"),
-            context.state.logger.clone(),
-        )?;
+    triggered.push((id, fsm));
 
-        // Listen to FSM for updates
-        let ct2 = context.clone();
-        tokio::spawn(update_notifications(ct2, id));
-        info!(context.state.logger, "Listening to state changes");
+    Ok(id)
+}
+
+// Resolves once all the dependencies of the index are available, or fails as soon as one of
+// them fails, or once the timeout is past. The database is checked periodically, and each time
+// a notification comes in.
+async fn wait_for_dependencies(
+    context: Context,
+    index_id: EntityId,
+    dependencies: Vec<EntityId>,
+    mut zmq: Subscribe,
+) -> Result<(), error::Error> {
+    let settings = &context.state.settings.dependencies;
+    let interval = Duration::from_secs(settings.poll_interval);
+    let deadline = Instant::now() + Duration::from_secs(settings.timeout);
+    loop {
+        let dependencies_status = dependencies_db(&context, index_id)
+            .await?
+            .into_iter()
+            .map(Index::from)
+            .collect::<Vec<_>>();
+        if let Some(dependency) = dependencies_status.iter().find(|index| index.has_failed()) {
+            return Err(error::Error::MiscError {
+                details: format!("Dependency {} failed", dependency.index_id),
+            });
+        }
+        if dependencies_status.iter().all(Index::is_available) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(error::Error::TimeoutError {
+                details: format!(
+                    "Dependencies of index {} not available after {}s",
+                    index_id, settings.timeout
+                ),
+            });
+        }
 
-        tokio::spawn(fsm::exec(fsm));
-        info!(context.state.logger, "Running FSM");
+        match tokio::time::timeout(interval, zmq.next()).await {
+            Ok(Some(msg)) => {
+                let msg = msg.context(error::ZMQRecvError {
+                    details: String::from("ZMQ Reception Error"),
+                })?;
+                // topic, id, status: we're only interested in the failure of a dependency,
+                // which we would not see in the database once it is reset.
+                let id = msg.get(1).and_then(|id| id.as_str()).map(str::parse::<i32>);
+                let status = msg
+                    .get(2)
+                    .and_then(|status| status.as_str())
+                    .map(serde_json::from_str::<fsm::State>);
+                if let (Some(Ok(id)), Some(Ok(status))) = (id, status) {
+                    if status.is_error() && dependencies.contains(&id) {
+                        return Err(error::Error::MiscError {
+                            details: format!("Dependency {} failed", id),
+                        });
+                    }
+                }
+            }
+            Ok(None) => {
+                return Err(error::Error::MiscError {
+                    details: String::from("Notifications stopped while waiting for dependencies"),
+                });
+            }
+            Err(_) => {} // Time to check the database again.
+        }
+    }
+}
 
-        Ok(IndexResponseBody { index })
+// Resolves once the index is made active, that is once the job running for the same index
+// type, data source and region is done. The database is checked periodically.
+async fn wait_for_turn(context: Context, index_id: EntityId) -> Result<(), error::Error> {
+    let interval = Duration::from_secs(context.state.settings.dependencies.poll_interval);
+    loop {
+        match activate_db(&context, index_id).await {
+            Err(error::Error::DBProvideError {
+                source: ProvideError::UniqueViolation { .. },
+                ..
+            }) => {}
+            res => return res,
+        }
+        tokio::time::delay_for(interval).await;
     }
-    .await
 }
 
-async fn update_notifications(context: Context, index_id: EntityId) -> Result<(), error::Error> {
-    // Ready a subscription connection to receive notifications from the FSM
+// Ready a subscription connection to receive notifications from the FSMs
+fn subscribe(context: &Context) -> Result<Subscribe, error::Error> {
     let zmq_endpoint = format!(
         "tcp://{}:{}",
         context.state.settings.zmq.host, context.state.settings.zmq.port
     );
     let zmq_topic = &context.state.settings.zmq.topic;
-    let mut zmq = async_zmq::subscribe(&zmq_endpoint)
+    let zmq = async_zmq::subscribe(&zmq_endpoint)
         .context(error::ZMQSocketError {
             details: format!("Could not subscribe to zmq endpoint at {}", &zmq_endpoint),
         })?
@@ -151,6 +1589,77 @@ async fn update_notifications(context: Context, index_id: EntityId) -> Result<()
         "Subscribed to ZMQ Publications on endpoint {} / topic {}", &zmq_endpoint, &zmq_topic
     );
 
+    Ok(zmq)
+}
+
+// Check the URL and format given for the 'url' data source, and make sure the
+// declared format can produce the requested index type.
+fn remote_dataset(
+    index_type: &str,
+    data_source: &str,
+    url: Option<String>,
+    format: Option<String>,
+    violations: &mut Violations,
+) -> Option<(Url, fsm::Format)> {
+    if data_source != "url" {
+        for (field, value) in &[("url", &url), ("format", &format)] {
+            if value.is_some() {
+                violations.add(
+                    UNEXPECTED_FIELD,
+                    &[*field],
+                    format!(
+                        "A {} can only be given with the url data source, not {}",
+                        field, data_source
+                    ),
+                );
+            }
+        }
+        return None;
+    }
+    let url = match url {
+        Some(url) => violations.check(
+            INVALID_URL,
+            &["url"],
+            Url::parse(&url).context(error::URLError {
+                details: format!("Could not parse dataset URL '{}'", url),
+            }),
+        ),
+        None => {
+            violations.add(MISSING_FIELD, &["url"], "The url data source needs a url");
+            None
+        }
+    };
+    let format = match format {
+        Some(format) => {
+            violations.check(INVALID_FORMAT, &["format"], format.parse::<fsm::Format>())
+        }
+        None => {
+            violations.add(
+                MISSING_FIELD,
+                &["format"],
+                "The url data source needs a format",
+            );
+            None
+        }
+    };
+    if let Some(format) = &format {
+        if !format.index_types().contains(&index_type) {
+            violations.add(
+                UNSUPPORTED_INDEX_TYPE,
+                &["indexType"],
+                format!("Cannot create a {} index from {}", index_type, format),
+            );
+        }
+    }
+    match (url, format) {
+        (Some(url), Some(format)) => Some((url, format)),
+        _ => None,
+    }
+}
+
+async fn update_notifications(context: Context, index_id: EntityId) -> Result<(), error::Error> {
+    let mut zmq = subscribe(&context)?;
+
     let logger = context.state.logger.clone();
     // and listen for notifications
     while let Some(msg) = zmq.next().await {
@@ -159,10 +1668,30 @@ async fn update_notifications(context: Context, index_id: EntityId) -> Result<()
             details: String::from("ZMQ Reception Error"),
         })?;
 
+        // All the FSMs publish on the same topic, so we skip what's not about our index.
+        let id = msg
+            .get(1)
+            .and_then(|id| id.as_str())
+            .and_then(|id| id.parse::<EntityId>().ok());
+        if id != Some(index_id) {
+            continue;
+        }
+
+        // A fourth part, when present, is the serialized record of what's new about the index.
+        let record = msg
+            .get(3)
+            .map(|record| {
+                record.as_str().ok_or(error::Error::MiscError {
+                    details: String::from("Record Message is not valid UTF8"),
+                })
+            })
+            .transpose()?
+            .map(String::from);
+
         // The msg we receive is made of three parts, the topic, the id, and the serialized status.
         // Here, we skip the topic, and extract the second part.
         let msg = msg
-            .get(2) // skip the topic and the id // FIXME use the id
+            .get(2) // skip the topic and the id
             .ok_or(error::Error::MiscError {
                 details: String::from("Just one item in a multipart message. That is plain wrong!"),
             })?
@@ -179,6 +1708,13 @@ async fn update_notifications(context: Context, index_id: EntityId) -> Result<()
 
         update_db(&context, index_id, msg).await?;
 
+        if let Some(record) = record {
+            let record = serde_json::from_str(&record).context(error::SerdeJSONError {
+                details: String::from("Could not deserialize record"),
+            })?;
+            update_record(&context, index_id, record).await?;
+        }
+
         match status {
             fsm::State::NotAvailable => {
                 break;
@@ -192,7 +1728,7 @@ async fn update_notifications(context: Context, index_id: EntityId) -> Result<()
     Ok(())
 }
 
-async fn update_db(
+pub(in crate::api) async fn update_db(
     context: &Context,
     index_id: EntityId,
     msg: &str,
@@ -208,6 +1744,13 @@ async fn update_db(
             details: "could not retrieve transaction",
         })?;
 
+    let previous = tx
+        .get_index(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: format!("Could not get index {}", index_id),
+        })?;
+
     let entity = tx
         .update_index_status(index_id, msg)
         .await
@@ -215,6 +1758,31 @@ async fn update_db(
             details: "Could not update index status",
         })?;
 
+    // Once its job is done, another index with the same type, data source and region can run.
+    let done = match serde_json::from_str::<fsm::State>(msg) {
+        Ok(fsm::State::Available) | Ok(fsm::State::NotAvailable) => true,
+        Ok(state) => state.is_error(),
+        Err(_) => false,
+    };
+    let entity = if done && entity.active {
+        tx.update_index_active(index_id, false)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not mark index as done",
+            })?
+    } else {
+        entity
+    };
+
+    // The FSM publishes its state again as it makes progress, we only keep the changes.
+    if state_type(&previous.status) != state_type(msg) {
+        tx.create_index_event(index_id, msg)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not record index state",
+            })?;
+    }
+
     tx.commit().await.context(error::DBError {
         details: "could not commit transaction",
     })?;
@@ -222,12 +1790,262 @@ async fn update_db(
     Ok(Index::from(entity))
 }
 
-async fn create_db(
+async fn update_record(
     context: &Context,
+    index_id: EntityId,
+    record: fsm::Record,
+) -> Result<(), error::Error> {
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    if let Some(metadata) = record.metadata {
+        tx.update_index_metadata(index_id, &metadata.to_string())
+            .await
+            .context(error::DBProvideError {
+                details: "Could not update index metadata",
+            })?;
+    }
+
+    if let Some(progress) = record.progress {
+        let progress = serde_json::to_string(&progress).context(error::SerdeJSONError {
+            details: String::from("Could not serialize progress"),
+        })?;
+        tx.update_index_progress(index_id, &progress)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not update index progress",
+            })?;
+    }
+
+    if let Some(provenance) = record.provenance {
+        let provenance = serde_json::to_string(&provenance).context(error::SerdeJSONError {
+            details: String::from("Could not serialize provenance"),
+        })?;
+        tx.update_index_provenance(index_id, &provenance)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not update index provenance",
+            })?;
+    }
+
+    if let (Some(es_index), Some(es_alias)) = (record.es_index, record.es_alias) {
+        tx.update_index_elasticsearch(index_id, &es_index, &es_alias)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not update index elasticsearch index",
+            })?;
+    }
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    Ok(())
+}
+
+// Create the index, along with its initial state and the key of the request.
+async fn insert_index(
+    conn: &mut SqliteConnection,
     index_type: &str,
     data_source: &str,
     region: &str,
+    options: &fsm::Options,
+    active: bool,
+    // The key of the request, and the request as JSON, if the client gave one.
+    idempotency: Option<(&str, &str)>,
 ) -> Result<Index, error::Error> {
+    let options = serde_json::to_string(options).context(error::SerdeJSONError {
+        details: String::from("Could not serialize options"),
+    })?;
+
+    let entity = conn
+        .create_index(&index_type, &data_source, &region, &options, active)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not create index",
+        })?;
+
+    conn.create_index_event(entity.index_id, &entity.status)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not record index state",
+        })?;
+
+    if let Some((key, request)) = idempotency {
+        conn.create_idempotency_key(key, entity.index_id, request)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not record idempotency key",
+            })?;
+    }
+
+    Ok(Index::from(entity))
+}
+
+// What became of a request for an index in the database.
+pub(in crate::api) enum Creation {
+    // The index was created, possibly queued behind the one running for the same type, data
+    // source and region.
+    Created {
+        index: Index,
+        queued_behind: Option<EntityId>,
+    },
+    // The request was already made, or the index running for the same type, data source and
+    // region is returned instead.
+    Existing(EntityId),
+}
+
+// Create the index requested, unless the request was already made with the same idempotency
+// key. Only one job runs at a time for an index type, a data source and a region, which the
+// database enforces: if one is running, the conflict policy of the request applies.
+pub(in crate::api) async fn insert_plan(
+    conn: &mut SqliteConnection,
+    context: &Context,
+    plan: &IndexPlan,
+) -> Result<Creation, error::Error> {
+    if let Some((key, request)) = plan.idempotency() {
+        if let Some(index_id) = find_replay(conn, context, key, request).await? {
+            return Ok(Creation::Existing(index_id));
+        }
+    }
+
+    let res = insert_index(
+        conn,
+        &plan.index_type,
+        &plan.data_source,
+        &plan.region,
+        &plan.options,
+        true,
+        plan.idempotency(),
+    )
+    .await;
+    let err = match res {
+        Ok(index) => {
+            return Ok(Creation::Created {
+                index,
+                queued_behind: None,
+            })
+        }
+        Err(
+            err @ error::Error::DBProvideError {
+                source: ProvideError::UniqueViolation { .. },
+                ..
+            },
+        ) => err,
+        Err(err) => return Err(err),
+    };
+
+    // The same request may have been committed with its key since we looked for it: it is
+    // replayed, whatever the conflict policy.
+    if let Some((key, request)) = plan.idempotency() {
+        if let Some(index_id) = find_replay(conn, context, key, request).await? {
+            return Ok(Creation::Existing(index_id));
+        }
+    }
+
+    let existing = conn
+        .get_active_index(&plan.index_type, &plan.data_source, &plan.region)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get active index",
+        })?;
+    let existing = match existing {
+        Some(existing) => existing,
+        None => return Err(err), // Not a conflict with a running index
+    };
+
+    match plan.on_conflict {
+        ConflictPolicy::Reject => Err(error::Error::ConflictError {
+            details: format!(
+                "Index {} is already running for {} {} {}",
+                existing.index_id, plan.index_type, plan.data_source, plan.region
+            ),
+        }),
+        ConflictPolicy::ReturnExisting => {
+            info!(
+                context.state.logger,
+                "Returning running index {}", existing.index_id
+            );
+            Ok(Creation::Existing(existing.index_id))
+        }
+        ConflictPolicy::Queue => {
+            info!(
+                context.state.logger,
+                "Queueing behind running index {}", existing.index_id
+            );
+            let index = insert_index(
+                conn,
+                &plan.index_type,
+                &plan.data_source,
+                &plan.region,
+                &plan.options,
+                false,
+                plan.idempotency(),
+            )
+            .await?;
+            Ok(Creation::Created {
+                index,
+                queued_behind: Some(existing.index_id),
+            })
+        }
+    }
+}
+
+// The index created by a previous request with the same key, within the retention window. The
+// key can't be reused for another request.
+async fn find_replay(
+    conn: &mut SqliteConnection,
+    context: &Context,
+    idempotency_key: &str,
+    request: &str,
+) -> Result<Option<EntityId>, error::Error> {
+    let retention = chrono::Duration::seconds(context.state.settings.idempotency.retention as i64);
+
+    conn.delete_idempotency_keys(Utc::now() - retention)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not delete expired idempotency keys",
+        })?;
+
+    let entity =
+        conn.get_idempotency_key(idempotency_key)
+            .await
+            .context(error::DBProvideError {
+                details: "Could not get idempotency key",
+            })?;
+
+    match entity {
+        Some(entity) if entity.request != request => Err(error::Error::ConflictError {
+            details: format!(
+                "Idempotency key '{}' was used for another request",
+                idempotency_key
+            ),
+        }),
+        Some(entity) => {
+            info!(
+                context.state.logger,
+                "Returning index {} created with idempotency key '{}'",
+                entity.index_id,
+                idempotency_key
+            );
+            Ok(Some(entity.index_id))
+        }
+        None => Ok(None),
+    }
+}
+
+async fn replay_index(
+    context: &Context,
+    idempotency_key: &str,
+    request: &str,
+) -> Result<Option<IndexResponseBody>, error::Error> {
     let pool = &context.state.pool;
     let mut tx = pool
         .conn()
@@ -237,16 +2055,193 @@ async fn create_db(
             details: "could not retrieve transaction",
         })?;
 
-    let entity = tx
-        .create_index(&index_type, &data_source, &region)
+    let index_id = find_replay(&mut tx, context, idempotency_key, request).await?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    match index_id {
+        Some(index_id) => get_index(index_id, context).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+// Let the job of the index run.
+async fn activate_db(context: &Context, index_id: EntityId) -> Result<(), error::Error> {
+    let pool = &context.state.pool;
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    tx.update_index_active(index_id, true)
         .await
         .context(error::DBProvideError {
-            details: "Could not create index",
+            details: format!("Could not activate index {}", index_id),
         })?;
 
     tx.commit().await.context(error::DBError {
         details: "could not commit transaction",
     })?;
 
-    Ok(Index::from(entity))
+    Ok(())
+}
+
+async fn create_dependency(
+    conn: &mut SqliteConnection,
+    index_id: EntityId,
+    dependency_id: EntityId,
+) -> Result<(), error::Error> {
+    conn.create_index_dependency(index_id, dependency_id)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not create index dependency",
+        })?;
+
+    Ok(())
+}
+
+async fn dependencies_db(
+    context: &Context,
+    index_id: EntityId,
+) -> Result<Vec<IndexEntity>, error::Error> {
+    let pool = &context.state.pool;
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    let entities = tx
+        .get_index_dependencies(index_id)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index dependencies",
+        })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    Ok(entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entity(index_id: EntityId, created_at: i64, updated_at: i64) -> IndexEntity {
+        IndexEntity {
+            index_id,
+            index_type: String::from("admins"),
+            data_source: String::from("cosmogony"),
+            region: String::from("ile-de-france"),
+            status: String::from(r#"{"type": "NotAvailable"}"#),
+            metadata: None,
+            options: None,
+            es_index: None,
+            es_alias: None,
+            progress: None,
+            provenance: None,
+            active: false,
+            bundle_id: None,
+            created_at: Utc.timestamp(created_at, 0),
+            updated_at: Utc.timestamp(updated_at, 0),
+        }
+    }
+
+    #[test]
+    fn cursors_hold_the_value_indexes_are_sorted_on() {
+        let index = entity(7, 1_600_000_000, 1_600_000_100);
+        assert_eq!(IndexCursor::new(&index, IndexSort::IndexId).value, 7);
+        assert_eq!(
+            IndexCursor::new(&index, IndexSort::CreatedAt).value,
+            1_600_000_000
+        );
+        assert_eq!(
+            IndexCursor::new(&index, IndexSort::UpdatedAt).value,
+            1_600_000_100
+        );
+    }
+
+    #[test]
+    fn parses_the_cursors_it_formats() {
+        let index = entity(7, 1_600_000_000, 1_600_000_100);
+        for sort in &[
+            IndexSort::IndexId,
+            IndexSort::CreatedAt,
+            IndexSort::UpdatedAt,
+        ] {
+            let cursor = IndexCursor::new(&index, *sort);
+            assert_eq!(parse_cursor(&format_cursor(cursor)).unwrap(), cursor);
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_cursors() {
+        for cursor in &["", "12", "12:", ":7", "abc:7", "12:x", "12:7:3", "12.5:7"] {
+            assert!(parse_cursor(cursor).is_err(), "'{}' was accepted", cursor);
+        }
+    }
+
+    #[test]
+    fn violations_hold_the_paths_of_the_fields() {
+        let mut violations = Violations::default();
+        assert!(violations.is_empty());
+
+        violations.add(MISSING_FIELD, &["region"], "The region can't be empty");
+        assert_eq!(
+            violations.check(INVALID_URL, &["url"], Ok::<_, error::Error>(3)),
+            Some(3)
+        );
+        let res = Err::<(), _>(error::Error::MiscError {
+            details: String::from("not a url"),
+        });
+        assert_eq!(violations.check(INVALID_URL, &["url"], res), None);
+
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations.0[0].code, MISSING_FIELD);
+        assert_eq!(violations.0[0].path, vec!["region"]);
+        assert_eq!(violations.0[1].code, INVALID_URL);
+        assert_eq!(violations.0[1].path, vec!["url"]);
+        assert_eq!(violations.0[1].message, "Misc Error: not a url");
+    }
+
+    #[test]
+    fn violations_are_prefixed_with_the_path_of_the_request() {
+        let mut violations = Violations::default();
+        violations.add(MISSING_FIELD, &["region"], "The region can't be empty");
+        violations.add(INVALID_OPTION, &["options", "concurrency"], "Not a number");
+
+        match error::Error::validation(violations.0).within(&["profile", "2"]) {
+            error::Error::ValidationError {
+                details,
+                violations,
+            } => {
+                assert_eq!(violations[0].path, vec!["profile", "2", "region"]);
+                assert_eq!(
+                    violations[1].path,
+                    vec!["profile", "2", "options", "concurrency"]
+                );
+                assert_eq!(
+                    details,
+                    "profile.2.region: The region can't be empty; \
+                     profile.2.options.concurrency: Not a number"
+                );
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        let err = error::Error::MiscError {
+            details: String::from("oops"),
+        };
+        assert_eq!(err.within(&["profile", "2"]).code(), "INTERNAL_ERROR");
+    }
 }
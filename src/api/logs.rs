@@ -0,0 +1,101 @@
+use futures::stream::{self, Stream, StreamExt};
+use juniper::{FieldError, GraphQLObject, IntoFieldError};
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use tokio::sync::broadcast::RecvError;
+
+use crate::api::gql::Context;
+use crate::db::model::EntityId;
+use crate::error;
+use crate::fsm;
+
+/// A line of output of a tool run for an index
+#[derive(Debug, Serialize, GraphQLObject)]
+pub struct IndexLogLine {
+    pub index_id: EntityId,
+    /// The step of the pipeline (download, processing, indexing or validation)
+    pub step: String,
+    /// stdout or stderr
+    pub stream: String,
+    pub line: String,
+}
+
+impl IndexLogLine {
+    fn new(index_id: EntityId, line: fsm::LogLine) -> Self {
+        let fsm::LogLine { step, stream, line } = line;
+        IndexLogLine {
+            index_id,
+            step,
+            stream,
+            line,
+        }
+    }
+}
+
+/// The response body for the logs of an index
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexLogsResponseBody {
+    lines: Vec<IndexLogLine>,
+    lines_count: i32,
+}
+
+impl From<Vec<IndexLogLine>> for IndexLogsResponseBody {
+    fn from(lines: Vec<IndexLogLine>) -> Self {
+        let lines_count = i32::try_from(lines.len()).unwrap();
+        Self { lines, lines_count }
+    }
+}
+
+pub type IndexLogStream = Pin<Box<dyn Stream<Item = Result<IndexLogLine, FieldError>> + Send>>;
+
+/// Retrieve the last 'tail' lines logged by the tools of an index, for a step or all of them.
+pub async fn index_logs(
+    index_id: EntityId,
+    step: Option<String>,
+    tail: Option<i32>,
+    context: &Context,
+) -> Result<IndexLogsResponseBody, error::Error> {
+    let tail = tail
+        .map(|tail| {
+            usize::try_from(tail).map_err(|_| error::Error::MiscError {
+                details: format!("Invalid tail {}, expected a positive number", tail),
+            })
+        })
+        .transpose()?;
+    let lines = context
+        .state
+        .logs
+        .read(index_id, step.as_deref(), tail)?
+        .into_iter()
+        .map(|line| IndexLogLine::new(index_id, line))
+        .collect::<Vec<_>>();
+    Ok(IndexLogsResponseBody::from(lines))
+}
+
+/// Follow the lines logged by the tools of an index, until it is done. Lines logged before the
+/// subscription are available with index_logs.
+pub fn follow_index_logs(index_id: EntityId, context: &Context) -> IndexLogStream {
+    match context.state.logs.follow(index_id) {
+        Some(receiver) => {
+            let lines = stream::unfold(receiver, move |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(line) => return Some((Ok(IndexLogLine::new(index_id, line)), receiver)),
+                        // We'd rather skip lines than hold back the tool.
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            });
+            Box::pin(lines)
+        }
+        None => {
+            let err = error::Error::MiscError {
+                details: format!("Index {} is not running", index_id),
+            };
+            Box::pin(stream::once(async move { Err(err.into_field_error()) }))
+        }
+    }
+}
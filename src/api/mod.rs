@@ -2,9 +2,24 @@
 /// See the [API Spec](https://github.com/gothinkster/realworld/tree/master/api#json-objects-returned-by-api)
 pub mod model;
 
+/// The state of indexes, as GraphQL types
+pub mod status;
+
 /// Route handlers for indexes
 pub mod indexes;
 
+/// Route handlers for region bundles
+pub mod bundles;
+
+/// Route handlers for the logs of the indexing tools
+pub mod logs;
+
+/// Route handlers for the indexing tools
+pub mod tools;
+
+/// Route handlers for the statistics computed from the history of indexes
+pub mod stats;
+
 /// Utility functions and traits
 pub mod utils;
 
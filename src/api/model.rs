@@ -2,12 +2,11 @@ use chrono::{DateTime, Utc};
 use juniper::GraphQLObject;
 use serde::Serialize;
 
+use crate::api::status::IndexState;
 use crate::db::model::*;
+use crate::fsm;
 
 /// An index
-// The status is a string, but it should be a state (as in FSM::State).
-// But for this to work, I'd have to implement GraphQLEnum for FSM::State,
-// and the enum would have to be anonymous.
 #[derive(Debug, Serialize, GraphQLObject)]
 #[serde(rename_all = "camelCase")]
 pub(in crate::api) struct Index {
@@ -15,7 +14,32 @@ pub(in crate::api) struct Index {
     pub index_type: String,
     pub data_source: String,
     pub region: String,
+    /// The state of the index, as a JSON string. Prefer 'state'.
     pub status: String,
+    /// The state of the index.
+    pub state: Option<IndexState>,
+    /// Metadata of the dataset (eg license, validity dates), as a JSON string.
+    pub metadata: Option<String>,
+    /// The options given to the tools, as a JSON string.
+    pub options: Option<String>,
+    /// The mimirsbrunn dataset the index was created with.
+    pub dataset: Option<String>,
+    /// The elasticsearch index holding the data, once indexed.
+    pub es_index: Option<String>,
+    /// The elasticsearch alias pointing to 'es_index'.
+    pub es_alias: Option<String>,
+    /// The last progress reported by the tools while indexing (phase, count, percentage), as
+    /// a JSON string.
+    pub progress: Option<String>,
+    /// Where the data comes from, and how it was indexed.
+    pub provenance: Option<Provenance>,
+    /// The indexes which must be available before this one can be indexed (eg the admins of
+    /// the region, for streets and addresses).
+    pub dependencies: Vec<EntityId>,
+    /// The region bundle this index was created for, if any.
+    pub bundle_id: Option<EntityId>,
+    /// The states the index went through, oldest first.
+    pub history: Vec<IndexEvent>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -28,17 +52,218 @@ impl From<IndexEntity> for Index {
             data_source,
             region,
             status,
+            metadata,
+            options,
+            es_index,
+            es_alias,
+            progress,
+            provenance,
+            bundle_id,
             created_at,
             updated_at,
-            ..
         } = entity;
 
+        let dataset = options
+            .as_ref()
+            .and_then(|options| serde_json::from_str::<fsm::Options>(options).ok())
+            .and_then(|options| options.dataset);
+
+        let provenance = provenance
+            .as_ref()
+            .and_then(|provenance| serde_json::from_str::<fsm::Provenance>(provenance).ok())
+            .map(Provenance::from);
+
+        let state = IndexState::parse(&status);
+
         Index {
             index_id,
             index_type,
             data_source,
             region,
             status,
+            state,
+            metadata,
+            options,
+            dataset,
+            es_index,
+            es_alias,
+            progress,
+            provenance,
+            dependencies: Vec::new(),
+            bundle_id,
+            history: Vec::new(),
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+impl Index {
+    pub fn is_available(&self) -> bool {
+        matches!(
+            serde_json::from_str::<fsm::State>(&self.status),
+            Ok(fsm::State::Available)
+        )
+    }
+
+    // Whether the FSM of the index stopped on an error. After publishing the error, the FSM
+    // resets to NotAvailable, which we can tell apart from a new index, since it has been
+    // updated since its creation.
+    pub fn has_failed(&self) -> bool {
+        match serde_json::from_str::<fsm::State>(&self.status) {
+            Ok(fsm::State::NotAvailable) => self.updated_at > self.created_at,
+            Ok(status) => status.is_error(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// A state an index went through
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub(in crate::api) struct IndexEvent {
+    /// The state, as a JSON string.
+    pub status: String,
+    pub state: Option<IndexState>,
+    /// When the index entered the state
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<IndexEventEntity> for IndexEvent {
+    fn from(entity: IndexEventEntity) -> Self {
+        let state = IndexState::parse(&entity.status);
+        IndexEvent {
+            status: entity.status,
+            state,
+            created_at: entity.created_at,
+        }
+    }
+}
+
+/// Where the data of an index comes from, and how it was indexed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub(in crate::api) struct Provenance {
+    /// The datasets given to the tools
+    pub inputs: Vec<ProvenanceInput>,
+    /// The tools run, in order
+    pub commands: Vec<ProvenanceCommand>,
+    /// The steps completed, in order
+    pub steps: Vec<ProvenanceStep>,
+}
+
+/// A dataset given to the tools
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub(in crate::api) struct ProvenanceInput {
+    /// Where the dataset was downloaded from, if it was not staged on disk
+    pub url: Option<String>,
+    pub file: String,
+    /// The size of the file, in bytes
+    pub size: f64,
+    pub sha256: Option<String>,
+    /// The Last-Modified header of the download, if any
+    pub last_modified: Option<String>,
+}
+
+/// A tool run for the index
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub(in crate::api) struct ProvenanceCommand {
+    pub step: String,
+    pub tool: String,
+    /// As told by '<tool> --version'
+    pub version: Option<String>,
+    pub command_line: String,
+    /// How long the tool ran, in seconds
+    pub duration: f64,
+    pub success: bool,
+}
+
+/// A step of the pipeline completed for the index
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub(in crate::api) struct ProvenanceStep {
+    pub step: String,
+    /// How long the step took, in seconds
+    pub duration: f64,
+}
+
+impl From<fsm::Provenance> for Provenance {
+    fn from(provenance: fsm::Provenance) -> Self {
+        let fsm::Provenance {
+            inputs,
+            commands,
+            steps,
+        } = provenance;
+
+        Provenance {
+            inputs: inputs
+                .into_iter()
+                .map(|input| ProvenanceInput {
+                    url: input.url,
+                    file: input.file.display().to_string(),
+                    size: input.size as f64,
+                    sha256: input.sha256,
+                    last_modified: input.last_modified,
+                })
+                .collect(),
+            commands: commands
+                .into_iter()
+                .map(|command| ProvenanceCommand {
+                    step: command.step,
+                    tool: command.tool,
+                    version: command.version,
+                    command_line: command.command_line,
+                    duration: command.duration.as_secs_f64(),
+                    success: command.success,
+                })
+                .collect(),
+            steps: steps
+                .into_iter()
+                .map(|step| ProvenanceStep {
+                    step: step.step,
+                    duration: step.duration.as_secs_f64(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A set of indexes created together for a region, following a profile.
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub(in crate::api) struct Bundle {
+    pub bundle_id: EntityId,
+    pub region: String,
+    /// The profile listing the indexes of the bundle.
+    pub profile: String,
+    /// The status of the bundle as a whole: DownloadingInProgress or DownloadingError while
+    /// downloading the dataset shared by the indexes, then InProgress, Failed or Available,
+    /// depending on the status of the indexes.
+    pub status: String,
+    pub indexes: Vec<Index>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<BundleEntity> for Bundle {
+    fn from(entity: BundleEntity) -> Self {
+        let BundleEntity {
+            bundle_id,
+            region,
+            profile,
+            status,
+            created_at,
+            updated_at,
+        } = entity;
+
+        Bundle {
+            bundle_id,
+            region,
+            profile,
+            status,
+            indexes: Vec::new(),
             created_at,
             updated_at,
         }
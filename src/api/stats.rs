@@ -0,0 +1,195 @@
+use futures::TryFutureExt;
+use juniper::GraphQLObject;
+use serde::Serialize;
+use snafu::ResultExt;
+use sqlx::Connection;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::api::gql::Context;
+use crate::api::indexes::IndexFilter;
+use crate::db::model::{EntityId, IndexEventEntity, ProvideData};
+use crate::db::Db;
+use crate::error;
+use crate::fsm;
+
+/// How long a step of the pipeline takes, computed from the history of indexes
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct StepDurations {
+    /// download, processing, indexing or validation
+    pub step: String,
+    /// How many times the step completed
+    pub count: i32,
+    /// How many times the step failed or timed out
+    pub failures: i32,
+    /// The durations of the completed steps, in seconds
+    pub min: Option<f64>,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl StepDurations {
+    fn new(step: &str, mut durations: Vec<f64>, failures: usize) -> Self {
+        durations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = durations.len();
+        let mean = if count > 0 {
+            Some(durations.iter().sum::<f64>() / count as f64)
+        } else {
+            None
+        };
+        let median = match count {
+            0 => None,
+            n if n % 2 == 1 => Some(durations[n / 2]),
+            n => Some((durations[n / 2 - 1] + durations[n / 2]) / 2.0),
+        };
+        StepDurations {
+            step: String::from(step),
+            count: i32::try_from(count).unwrap(),
+            failures: i32::try_from(failures).unwrap(),
+            min: durations.first().copied(),
+            mean,
+            median,
+            max: durations.last().copied(),
+        }
+    }
+}
+
+/// The response body for the durations of the steps
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct StepDurationsResponseBody {
+    steps: Vec<StepDurations>,
+    /// The number of indexes the durations are computed from
+    indexes_count: i32,
+}
+
+/// The durations of the steps of the indexes matching the filter, computed from the states they
+/// went through: a step lasts from the state in progress to the next one.
+pub async fn step_durations(
+    filter: Option<IndexFilter>,
+    context: &Context,
+) -> Result<StepDurationsResponseBody, error::Error> {
+    let query = filter.unwrap_or_default().query();
+    let pool = &context.state.pool;
+
+    let mut tx = pool
+        .conn()
+        .and_then(Connection::begin)
+        .await
+        .context(error::DBError {
+            details: "could not retrieve transaction",
+        })?;
+
+    let entities = tx
+        .find_indexes(&query)
+        .await
+        .context(error::DBProvideError {
+            details: "Could not find indexes",
+        })?;
+
+    let events = tx
+        .get_all_index_events()
+        .await
+        .context(error::DBProvideError {
+            details: "Could not get index history",
+        })?;
+
+    tx.commit().await.context(error::DBError {
+        details: "could not commit transaction",
+    })?;
+
+    let mut history = entities
+        .iter()
+        .map(|entity| (entity.index_id, Vec::new()))
+        .collect::<HashMap<EntityId, Vec<IndexEventEntity>>>();
+    for event in events {
+        if let Some(events) = history.get_mut(&event.index_id) {
+            events.push(event);
+        }
+    }
+
+    let mut durations = HashMap::<&str, Vec<f64>>::new();
+    let mut failures = HashMap::<&str, usize>::new();
+    for events in history.values() {
+        // The events are sorted, oldest first.
+        for pair in events.windows(2) {
+            let states = (
+                serde_json::from_str::<fsm::State>(&pair[0].status),
+                serde_json::from_str::<fsm::State>(&pair[1].status),
+            );
+            let (step, next) = match states {
+                (Ok(state), Ok(next)) => match state.step() {
+                    Some(step) => (step, next),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if next.is_error() {
+                *failures.entry(step).or_default() += 1;
+            } else {
+                let duration = pair[1].created_at - pair[0].created_at;
+                durations
+                    .entry(step)
+                    .or_default()
+                    .push(duration.num_seconds() as f64);
+            }
+        }
+    }
+
+    let steps = fsm::STEPS
+        .iter()
+        .map(|step| {
+            StepDurations::new(
+                step,
+                durations.remove(step).unwrap_or_default(),
+                failures.get(step).copied().unwrap_or_default(),
+            )
+        })
+        .collect();
+
+    Ok(StepDurationsResponseBody {
+        steps,
+        indexes_count: i32::try_from(history.len()).unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_durations_without_completed_steps() {
+        let stats = StepDurations::new("download", Vec::new(), 2);
+        assert_eq!(stats.step, "download");
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.failures, 2);
+        assert_eq!(stats.min, None);
+        assert_eq!(stats.mean, None);
+        assert_eq!(stats.median, None);
+        assert_eq!(stats.max, None);
+    }
+
+    #[test]
+    fn step_durations_with_an_odd_count() {
+        let stats = StepDurations::new("processing", vec![30.0, 10.0, 20.0], 0);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.failures, 0);
+        assert_eq!(stats.min, Some(10.0));
+        assert_eq!(stats.mean, Some(20.0));
+        assert_eq!(stats.median, Some(20.0));
+        assert_eq!(stats.max, Some(30.0));
+    }
+
+    #[test]
+    fn step_durations_with_an_even_count() {
+        let stats = StepDurations::new("indexing", vec![40.0, 10.0, 30.0, 0.0], 1);
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.min, Some(0.0));
+        assert_eq!(stats.mean, Some(20.0));
+        assert_eq!(stats.median, Some(20.0));
+        assert_eq!(stats.max, Some(40.0));
+    }
+}
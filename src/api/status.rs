@@ -0,0 +1,335 @@
+use chrono::{DateTime, Utc};
+use juniper::{GraphQLObject, GraphQLUnion};
+use serde::Serialize;
+
+use crate::db::model::EntityId;
+use crate::fsm;
+
+/// The state of an index, as its FSM goes through the steps of the pipeline
+#[derive(Debug, Serialize, GraphQLUnion)]
+#[serde(untagged)]
+pub enum IndexState {
+    NotAvailable(NotAvailableState),
+    WaitingForDependencies(WaitingForDependenciesState),
+    DependencyError(DependencyErrorState),
+    DownloadingInProgress(DownloadingInProgressState),
+    DownloadingError(DownloadingErrorState),
+    Downloaded(DownloadedState),
+    ProcessingInProgress(ProcessingInProgressState),
+    ProcessingError(ProcessingErrorState),
+    Processed(ProcessedState),
+    IndexingInProgress(IndexingInProgressState),
+    IndexingError(IndexingErrorState),
+    Indexed(IndexedState),
+    ValidationInProgress(ValidationInProgressState),
+    ValidationError(ValidationErrorState),
+    Available(AvailableState),
+    TimedOut(TimedOutState),
+    Failure(FailureState),
+}
+
+/// The index has not started, or has been reset after an error
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct NotAvailableState {
+    /// Always 'NotAvailable'
+    pub state_type: String,
+}
+
+/// The index waits for other indexes to be available
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct WaitingForDependenciesState {
+    /// Always 'WaitingForDependencies'
+    pub state_type: String,
+    /// The indexes it waits for
+    pub dependencies: Vec<EntityId>,
+}
+
+/// One of the indexes it waited for failed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyErrorState {
+    /// Always 'DependencyError'
+    pub state_type: String,
+    pub details: String,
+}
+
+/// The dataset is being downloaded
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadingInProgressState {
+    /// Always 'DownloadingInProgress'
+    pub state_type: String,
+    pub started_at: DateTime<Utc>,
+    pub progress: Option<IndexProgress>,
+}
+
+/// The dataset could not be downloaded
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadingErrorState {
+    /// Always 'DownloadingError'
+    pub state_type: String,
+    pub details: String,
+}
+
+/// The dataset has been downloaded
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadedState {
+    /// Always 'Downloaded'
+    pub state_type: String,
+    pub file_path: String,
+    /// How long the download took, in seconds
+    pub duration: f64,
+}
+
+/// The dataset is being processed (eg by cosmogony, or converted from GTFS to NTFS)
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessingInProgressState {
+    /// Always 'ProcessingInProgress'
+    pub state_type: String,
+    pub file_path: String,
+    pub started_at: DateTime<Utc>,
+    pub progress: Option<IndexProgress>,
+}
+
+/// The dataset could not be processed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessingErrorState {
+    /// Always 'ProcessingError'
+    pub state_type: String,
+    pub details: String,
+}
+
+/// The dataset has been processed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessedState {
+    /// Always 'Processed'
+    pub state_type: String,
+    pub file_path: String,
+    /// How long the processing took, in seconds
+    pub duration: f64,
+}
+
+/// The dataset is being indexed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexingInProgressState {
+    /// Always 'IndexingInProgress'
+    pub state_type: String,
+    pub file_path: String,
+    pub started_at: DateTime<Utc>,
+    pub progress: Option<IndexProgress>,
+}
+
+/// The dataset could not be indexed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexingErrorState {
+    /// Always 'IndexingError'
+    pub state_type: String,
+    pub details: String,
+}
+
+/// The dataset has been indexed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedState {
+    /// Always 'Indexed'
+    pub state_type: String,
+    /// How long the indexing took, in seconds
+    pub duration: f64,
+}
+
+/// The index is being validated
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationInProgressState {
+    /// Always 'ValidationInProgress'
+    pub state_type: String,
+}
+
+/// The index did not pass validation
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationErrorState {
+    /// Always 'ValidationError'
+    pub state_type: String,
+    pub details: String,
+}
+
+/// The index is available
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailableState {
+    /// Always 'Available'
+    pub state_type: String,
+}
+
+/// A tool did not complete its step in time, and was killed
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct TimedOutState {
+    /// Always 'TimedOut'
+    pub state_type: String,
+    pub step: String,
+    pub details: String,
+}
+
+/// The FSM of the index went wrong
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct FailureState {
+    /// Always 'Failure'
+    pub state_type: String,
+    pub details: String,
+}
+
+/// How far an index is in the current step, when known
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexProgress {
+    /// What is being done, eg 'Downloaded BANO for department 75', or 'Indexed street'
+    pub phase: String,
+    /// How many items have been processed so far
+    pub count: f64,
+    /// How many items there are to process
+    pub total: Option<f64>,
+    pub percentage: Option<f64>,
+}
+
+impl From<fsm::Progress> for IndexProgress {
+    fn from(progress: fsm::Progress) -> Self {
+        IndexProgress {
+            phase: progress.phase,
+            count: progress.count as f64,
+            total: progress.total.map(|total| total as f64),
+            percentage: progress.percentage,
+        }
+    }
+}
+
+impl From<fsm::State> for IndexState {
+    fn from(state: fsm::State) -> Self {
+        match state {
+            fsm::State::NotAvailable => IndexState::NotAvailable(NotAvailableState {
+                state_type: String::from("NotAvailable"),
+            }),
+            fsm::State::WaitingForDependencies { dependencies } => {
+                IndexState::WaitingForDependencies(WaitingForDependenciesState {
+                    state_type: String::from("WaitingForDependencies"),
+                    dependencies,
+                })
+            }
+            fsm::State::DependencyError { details } => {
+                IndexState::DependencyError(DependencyErrorState {
+                    state_type: String::from("DependencyError"),
+                    details,
+                })
+            }
+            fsm::State::DownloadingInProgress {
+                started_at,
+                progress,
+            } => IndexState::DownloadingInProgress(DownloadingInProgressState {
+                state_type: String::from("DownloadingInProgress"),
+                started_at: DateTime::<Utc>::from(started_at),
+                progress: progress.map(IndexProgress::from),
+            }),
+            fsm::State::DownloadingError { details } => {
+                IndexState::DownloadingError(DownloadingErrorState {
+                    state_type: String::from("DownloadingError"),
+                    details,
+                })
+            }
+            fsm::State::Downloaded {
+                file_path,
+                duration,
+            } => IndexState::Downloaded(DownloadedState {
+                state_type: String::from("Downloaded"),
+                file_path: file_path.display().to_string(),
+                duration: duration.as_secs_f64(),
+            }),
+            fsm::State::ProcessingInProgress {
+                file_path,
+                started_at,
+                progress,
+            } => IndexState::ProcessingInProgress(ProcessingInProgressState {
+                state_type: String::from("ProcessingInProgress"),
+                file_path: file_path.display().to_string(),
+                started_at: DateTime::<Utc>::from(started_at),
+                progress: progress.map(IndexProgress::from),
+            }),
+            fsm::State::ProcessingError { details } => {
+                IndexState::ProcessingError(ProcessingErrorState {
+                    state_type: String::from("ProcessingError"),
+                    details,
+                })
+            }
+            fsm::State::Processed {
+                file_path,
+                duration,
+            } => IndexState::Processed(ProcessedState {
+                state_type: String::from("Processed"),
+                file_path: file_path.display().to_string(),
+                duration: duration.as_secs_f64(),
+            }),
+            fsm::State::IndexingInProgress {
+                file_path,
+                started_at,
+                progress,
+            } => IndexState::IndexingInProgress(IndexingInProgressState {
+                state_type: String::from("IndexingInProgress"),
+                file_path: file_path.display().to_string(),
+                started_at: DateTime::<Utc>::from(started_at),
+                progress: progress.map(IndexProgress::from),
+            }),
+            fsm::State::IndexingError { details } => {
+                IndexState::IndexingError(IndexingErrorState {
+                    state_type: String::from("IndexingError"),
+                    details,
+                })
+            }
+            fsm::State::Indexed { duration } => IndexState::Indexed(IndexedState {
+                state_type: String::from("Indexed"),
+                duration: duration.as_secs_f64(),
+            }),
+            fsm::State::ValidationInProgress => {
+                IndexState::ValidationInProgress(ValidationInProgressState {
+                    state_type: String::from("ValidationInProgress"),
+                })
+            }
+            fsm::State::ValidationError { details } => {
+                IndexState::ValidationError(ValidationErrorState {
+                    state_type: String::from("ValidationError"),
+                    details,
+                })
+            }
+            fsm::State::Available => IndexState::Available(AvailableState {
+                state_type: String::from("Available"),
+            }),
+            fsm::State::TimedOut { step, details } => IndexState::TimedOut(TimedOutState {
+                state_type: String::from("TimedOut"),
+                step,
+                details,
+            }),
+            fsm::State::Failure(details) => IndexState::Failure(FailureState {
+                state_type: String::from("Failure"),
+                details,
+            }),
+        }
+    }
+}
+
+impl IndexState {
+    // The state of an index, from its serialized status. None if it can't be read.
+    pub fn parse(status: &str) -> Option<Self> {
+        serde_json::from_str::<fsm::State>(status)
+            .ok()
+            .map(IndexState::from)
+    }
+}
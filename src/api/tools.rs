@@ -0,0 +1,64 @@
+use juniper::GraphQLObject;
+use serde::Serialize;
+use std::convert::TryFrom;
+
+use crate::api::gql::Context;
+
+/// An external tool, as checked when the service started
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub name: String,
+    pub path: String,
+    /// The version reported by the tool
+    pub version: Option<String>,
+    /// Why the tool can't be used (missing, not executable, incompatible)
+    pub error: Option<String>,
+    /// The data sources which need the tool
+    pub data_sources: Vec<String>,
+}
+
+/// The response body for multiple tools
+#[derive(Debug, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct MultToolsResponseBody {
+    tools: Vec<Tool>,
+    tools_count: i32,
+}
+
+impl From<Vec<Tool>> for MultToolsResponseBody {
+    fn from(tools: Vec<Tool>) -> Self {
+        let tools_count = i32::try_from(tools.len()).unwrap();
+        Self { tools, tools_count }
+    }
+}
+
+/// Retrieve all the tools used by the data sources
+pub fn list_tools(context: &Context) -> MultToolsResponseBody {
+    let registry = &context.state.registry;
+    let tools = registry
+        .tools()
+        .into_iter()
+        .map(|check| {
+            let data_sources = registry
+                .names()
+                .into_iter()
+                .filter(|name| {
+                    registry
+                        .get(name)
+                        .map(|source| source.tools().contains(&check.name.as_str()))
+                        .unwrap_or(false)
+                })
+                .map(String::from)
+                .collect();
+            Tool {
+                name: check.name.clone(),
+                path: check.path.display().to_string(),
+                version: check.version.clone(),
+                error: check.error.clone(),
+                data_sources,
+            }
+        })
+        .collect::<Vec<_>>();
+    MultToolsResponseBody::from(tools)
+}
@@ -11,10 +11,104 @@ pub struct IndexEntity {
     pub data_source: String,
     pub region: String,
     pub status: String,
+    pub metadata: Option<String>,
+    pub options: Option<String>,
+    pub es_index: Option<String>,
+    pub es_alias: Option<String>,
+    pub progress: Option<String>,
+    pub provenance: Option<String>,
+    pub active: bool, // While its job runs, or waits to run
+    pub bundle_id: Option<EntityId>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+// A set of indexes created together for a region, following a profile.
+pub struct BundleEntity {
+    pub bundle_id: EntityId,
+    pub region: String,
+    pub profile: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// An edge of the dependency graph: 'index_id' cannot be indexed before 'dependency_id'
+// is available.
+pub struct IndexDependencyEntity {
+    pub index_id: EntityId,
+    pub dependency_id: EntityId,
+}
+
+// A state the FSM of an index went through, and when.
+pub struct IndexEventEntity {
+    pub event_id: EntityId,
+    pub index_id: EntityId,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// The index created by a request bearing a key, and the request itself, as JSON.
+pub struct IdempotencyKeyEntity {
+    pub idempotency_key: String,
+    pub index_id: EntityId,
+    pub request: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Which indexes to list, in which order, and how many. The filters which are given must all
+// match, and the ranges of dates are inclusive.
+#[derive(Debug, Clone, Default)]
+pub struct IndexQuery {
+    pub index_type: Option<String>,
+    pub data_source: Option<String>,
+    pub region: Option<String>,
+    pub state_type: Option<String>, // The type of the status, eg 'Available'
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub sort: IndexSort,
+    pub descending: bool,
+    pub after: Option<IndexCursor>, // Only the indexes after this one, in the order of 'sort'
+    pub limit: Option<u32>,
+}
+
+// The order of indexes. Indexes sorted on the same value are sorted by id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexSort {
+    IndexId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl Default for IndexSort {
+    fn default() -> Self {
+        IndexSort::UpdatedAt
+    }
+}
+
+// Where a page of indexes ends: the value the indexes are sorted on, and the id of the index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexCursor {
+    pub value: i64,
+    pub index_id: EntityId,
+}
+
+impl IndexCursor {
+    pub fn new(index: &IndexEntity, sort: IndexSort) -> Self {
+        let value = match sort {
+            IndexSort::IndexId => i64::from(index.index_id),
+            IndexSort::CreatedAt => index.created_at.timestamp(),
+            IndexSort::UpdatedAt => index.updated_at.timestamp(),
+        };
+        IndexCursor {
+            value,
+            index_id: index.index_id,
+        }
+    }
+}
+
 #[async_trait]
 pub trait ProvideData {
     async fn create_index(
@@ -26,15 +120,165 @@ pub trait ProvideData {
         // The example I am using is based on REST interface, which
         // is not typed.... but for GraphQL, it could be different
         region: &str,
+        // The options of the tools, as JSON, so that the run can be reproduced.
+        options: &str,
+        // Only one index can be active for an index type, a data source and a region, so
+        // creating a second one fails with a UniqueViolation.
+        active: bool,
     ) -> ProvideResult<IndexEntity>;
 
     async fn get_all_indexes(&mut self) -> ProvideResult<Vec<IndexEntity>>;
 
+    async fn get_index(&mut self, index_id: EntityId) -> ProvideResult<IndexEntity>;
+
+    // The indexes matching the query, in its order, and at most 'limit' of them.
+    async fn find_indexes(&mut self, query: &IndexQuery) -> ProvideResult<Vec<IndexEntity>>;
+
+    // How many indexes match the filters of the query, regardless of its cursor and limit.
+    async fn count_indexes(&mut self, query: &IndexQuery) -> ProvideResult<i64>;
+
     async fn update_index_status(
         &mut self,
         index_id: EntityId,
         status: &str,
     ) -> ProvideResult<IndexEntity>;
+
+    async fn update_index_metadata(
+        &mut self,
+        index_id: EntityId,
+        metadata: &str,
+    ) -> ProvideResult<IndexEntity>;
+
+    // The elasticsearch index holding the data, and the alias pointing to it.
+    async fn update_index_elasticsearch(
+        &mut self,
+        index_id: EntityId,
+        es_index: &str,
+        es_alias: &str,
+    ) -> ProvideResult<IndexEntity>;
+
+    // What the tools told us while indexing, as JSON.
+    async fn update_index_progress(
+        &mut self,
+        index_id: EntityId,
+        progress: &str,
+    ) -> ProvideResult<IndexEntity>;
+
+    // Where the data comes from, and how it was indexed, as JSON.
+    async fn update_index_provenance(
+        &mut self,
+        index_id: EntityId,
+        provenance: &str,
+    ) -> ProvideResult<IndexEntity>;
+
+    // Mark the job of the index as running (or waiting to run), or done. Fails with a
+    // UniqueViolation if another index is active for the same index type, data source and region.
+    async fn update_index_active(
+        &mut self,
+        index_id: EntityId,
+        active: bool,
+    ) -> ProvideResult<IndexEntity>;
+
+    // The index whose job runs for the index type, data source and region, if any.
+    async fn get_active_index(
+        &mut self,
+        index_type: &str,
+        data_source: &str,
+        region: &str,
+    ) -> ProvideResult<Option<IndexEntity>>;
+
+    // No job survives a restart of the service, so no index is active anymore.
+    async fn reset_active_indexes(&mut self) -> ProvideResult<()>;
+
+    // Delete the index, along with its history, its dependencies and its idempotency keys.
+    // Returns the index as it was.
+    async fn delete_index(&mut self, index_id: EntityId) -> ProvideResult<IndexEntity>;
+
+    // The most recently created index of the given type for the region, if any.
+    async fn get_latest_index(
+        &mut self,
+        index_type: &str,
+        region: &str,
+    ) -> ProvideResult<Option<IndexEntity>>;
+
+    async fn create_index_dependency(
+        &mut self,
+        index_id: EntityId,
+        dependency_id: EntityId,
+    ) -> ProvideResult<IndexDependencyEntity>;
+
+    // The indexes 'index_id' depends on.
+    async fn get_index_dependencies(
+        &mut self,
+        index_id: EntityId,
+    ) -> ProvideResult<Vec<IndexEntity>>;
+
+    async fn get_all_index_dependencies(&mut self) -> ProvideResult<Vec<IndexDependencyEntity>>;
+
+    // The indexes which depend on 'index_id'.
+    async fn get_index_dependents(&mut self, index_id: EntityId)
+        -> ProvideResult<Vec<IndexEntity>>;
+
+    // The dependencies of the indexes 'index_ids', eg those of a page of indexes.
+    async fn get_index_dependencies_of(
+        &mut self,
+        index_ids: &[EntityId],
+    ) -> ProvideResult<Vec<IndexDependencyEntity>>;
+
+    // Record the state the index is now in.
+    async fn create_index_event(
+        &mut self,
+        index_id: EntityId,
+        status: &str,
+    ) -> ProvideResult<IndexEventEntity>;
+
+    // The states 'index_id' went through, oldest first.
+    async fn get_index_events(
+        &mut self,
+        index_id: EntityId,
+    ) -> ProvideResult<Vec<IndexEventEntity>>;
+
+    async fn get_all_index_events(&mut self) -> ProvideResult<Vec<IndexEventEntity>>;
+
+    // The states the indexes 'index_ids' went through, by index, oldest first.
+    async fn get_index_events_of(
+        &mut self,
+        index_ids: &[EntityId],
+    ) -> ProvideResult<Vec<IndexEventEntity>>;
+
+    // Remember the index created by the request with the given key. Fails with a
+    // UniqueViolation if the key is already known.
+    async fn create_idempotency_key(
+        &mut self,
+        idempotency_key: &str,
+        index_id: EntityId,
+        request: &str,
+    ) -> ProvideResult<IdempotencyKeyEntity>;
+
+    async fn get_idempotency_key(
+        &mut self,
+        idempotency_key: &str,
+    ) -> ProvideResult<Option<IdempotencyKeyEntity>>;
+
+    // Forget the keys created before the given date.
+    async fn delete_idempotency_keys(&mut self, created_before: DateTime<Utc>)
+        -> ProvideResult<()>;
+
+    async fn create_bundle(&mut self, region: &str, profile: &str) -> ProvideResult<BundleEntity>;
+
+    async fn get_all_bundles(&mut self) -> ProvideResult<Vec<BundleEntity>>;
+
+    async fn update_bundle_status(
+        &mut self,
+        bundle_id: EntityId,
+        status: &str,
+    ) -> ProvideResult<BundleEntity>;
+
+    async fn update_index_bundle(
+        &mut self,
+        index_id: EntityId,
+        bundle_id: EntityId,
+    ) -> ProvideResult<IndexEntity>;
 }
 
 pub type ProvideResult<T> = Result<T, ProvideError>;
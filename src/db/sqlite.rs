@@ -1,10 +1,10 @@
 use async_trait::async_trait;
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use slog::{info, o, Logger};
 use snafu::ResultExt;
 use sqlx::error::DatabaseError;
 use sqlx::pool::PoolConnection;
-use sqlx::sqlite::{SqliteError, SqliteQueryAs};
+use sqlx::sqlite::{Sqlite, SqliteError, SqliteQueryAs};
 use sqlx::{Cursor, Executor, FromRow, SqliteConnection, SqlitePool};
 use std::convert::TryFrom;
 use std::process::Stdio;
@@ -25,7 +25,8 @@ impl TryFrom<&SqliteError> for ProvideError {
     /// * [Sqlite Error Codes](https://www.sqlite.org/rescode.html)
     fn try_from(db_err: &SqliteError) -> Result<Self, Self::Error> {
         let provider_err = match db_err.code().unwrap() {
-            "2067" => ProvideError::UniqueViolation {
+            // SQLITE_CONSTRAINT_UNIQUE, and SQLITE_CONSTRAINT_PRIMARYKEY (eg idempotency keys)
+            "2067" | "1555" => ProvideError::UniqueViolation {
                 details: db_err.message().to_owned(),
                 // FIXME Can't find a way to add a source
                 // source: err.into(),
@@ -46,6 +47,14 @@ struct SqliteIndexEntity {
     data_source: String,
     region: String,
     status: String,
+    metadata: Option<String>,
+    options: Option<String>,
+    es_index: Option<String>,
+    es_alias: Option<String>,
+    progress: Option<String>,
+    provenance: Option<String>,
+    active: bool,
+    bundle_id: Option<EntityId>,
     created_at: i32,
     updated_at: i32,
 }
@@ -58,6 +67,14 @@ impl From<SqliteIndexEntity> for IndexEntity {
             data_source,
             region,
             status,
+            metadata,
+            options,
+            es_index,
+            es_alias,
+            progress,
+            provenance,
+            active,
+            bundle_id,
             created_at,
             updated_at,
         } = entity;
@@ -68,6 +85,189 @@ impl From<SqliteIndexEntity> for IndexEntity {
             data_source,
             region,
             status,
+            metadata,
+            options,
+            es_index,
+            es_alias,
+            progress,
+            provenance,
+            active,
+            bundle_id,
+            created_at: Utc.timestamp(created_at as _, 0),
+            updated_at: Utc.timestamp(updated_at as _, 0),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SqliteCount {
+    count: i64,
+}
+
+// A value bound to a query built on the fly.
+enum SqlValue {
+    Text(String),
+    Integer(i64),
+}
+
+fn sort_column(sort: IndexSort) -> &'static str {
+    match sort {
+        IndexSort::IndexId => "index_id",
+        IndexSort::CreatedAt => "created_at",
+        IndexSort::UpdatedAt => "updated_at",
+    }
+}
+
+// The WHERE clause selecting the indexes matching the filters of the query (and, if 'paginate',
+// coming after its cursor), along with the values to bind, in order.
+fn index_conditions(query: &IndexQuery, paginate: bool) -> (String, Vec<SqlValue>) {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    let texts = [
+        ("index_type = ?", &query.index_type),
+        ("data_source = ?", &query.data_source),
+        ("region = ?", &query.region),
+        ("json_extract(status, '$.type') = ?", &query.state_type),
+    ];
+    for (condition, value) in texts.iter() {
+        if let Some(value) = value {
+            conditions.push(String::from(*condition));
+            values.push(SqlValue::Text(value.clone()));
+        }
+    }
+    let dates = [
+        ("created_at >= ?", &query.created_after),
+        ("created_at <= ?", &query.created_before),
+        ("updated_at >= ?", &query.updated_after),
+        ("updated_at <= ?", &query.updated_before),
+    ];
+    for (condition, value) in dates.iter() {
+        if let Some(value) = value {
+            conditions.push(String::from(*condition));
+            values.push(SqlValue::Integer(value.timestamp()));
+        }
+    }
+    if let (true, Some(after)) = (paginate, query.after) {
+        let column = sort_column(query.sort);
+        let op = if query.descending { "<" } else { ">" };
+        conditions.push(format!(
+            "({} {} ? OR ({} = ? AND index_id {} ?))",
+            column, op, column, op
+        ));
+        values.push(SqlValue::Integer(after.value));
+        values.push(SqlValue::Integer(after.value));
+        values.push(SqlValue::Integer(i64::from(after.index_id)));
+    }
+    if conditions.is_empty() {
+        (String::new(), values)
+    } else {
+        (format!("WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+// The placeholders of a list of 'count' values, for 'IN (...)'.
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+#[derive(sqlx::FromRow)]
+struct SqliteIndexDependencyEntity {
+    index_id: EntityId,
+    dependency_id: EntityId,
+}
+
+impl From<SqliteIndexDependencyEntity> for IndexDependencyEntity {
+    fn from(entity: SqliteIndexDependencyEntity) -> Self {
+        let SqliteIndexDependencyEntity {
+            index_id,
+            dependency_id,
+        } = entity;
+
+        IndexDependencyEntity {
+            index_id,
+            dependency_id,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SqliteIndexEventEntity {
+    event_id: EntityId,
+    index_id: EntityId,
+    status: String,
+    created_at: i32,
+}
+
+impl From<SqliteIndexEventEntity> for IndexEventEntity {
+    fn from(entity: SqliteIndexEventEntity) -> Self {
+        let SqliteIndexEventEntity {
+            event_id,
+            index_id,
+            status,
+            created_at,
+        } = entity;
+
+        IndexEventEntity {
+            event_id,
+            index_id,
+            status,
+            created_at: Utc.timestamp(created_at as _, 0),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SqliteIdempotencyKeyEntity {
+    idempotency_key: String,
+    index_id: EntityId,
+    request: String,
+    created_at: i32,
+}
+
+impl From<SqliteIdempotencyKeyEntity> for IdempotencyKeyEntity {
+    fn from(entity: SqliteIdempotencyKeyEntity) -> Self {
+        let SqliteIdempotencyKeyEntity {
+            idempotency_key,
+            index_id,
+            request,
+            created_at,
+        } = entity;
+
+        IdempotencyKeyEntity {
+            idempotency_key,
+            index_id,
+            request,
+            created_at: Utc.timestamp(created_at as _, 0),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SqliteBundleEntity {
+    bundle_id: EntityId,
+    region: String,
+    profile: String,
+    status: String,
+    created_at: i32,
+    updated_at: i32,
+}
+
+impl From<SqliteBundleEntity> for BundleEntity {
+    fn from(entity: SqliteBundleEntity) -> Self {
+        let SqliteBundleEntity {
+            bundle_id,
+            region,
+            profile,
+            status,
+            created_at,
+            updated_at,
+        } = entity;
+
+        BundleEntity {
+            bundle_id,
+            region,
+            profile,
+            status,
             created_at: Utc.timestamp(created_at as _, 0),
             updated_at: Utc.timestamp(updated_at as _, 0),
         }
@@ -95,17 +295,21 @@ impl ProvideData for SqliteConnection {
         index_type: &str,
         data_source: &str,
         region: &str,
+        options: &str,
+        active: bool,
     ) -> ProvideResult<IndexEntity> {
         let rec: SqliteIndexEntity = sqlx::query_as(
             r#"
-INSERT INTO indexes ( index_type, data_source, region )
-VALUES ( $1, $2, $3 );
+INSERT INTO indexes ( index_type, data_source, region, options, active )
+VALUES ( $1, $2, $3, $4, $5 );
 SELECT * FROM indexes WHERE index_id = last_insert_rowid();
             "#,
         )
         .bind(index_type)
         .bind(data_source)
         .bind(region)
+        .bind(options)
+        .bind(active)
         .fetch_one(self)
         .await?;
 
@@ -152,6 +356,160 @@ SELECT * FROM indexes WHERE index_id = $1
         Ok(rec.into())
     }
 
+    async fn update_index_metadata(
+        &mut self,
+        index_id: EntityId,
+        metadata: &str,
+    ) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT update_index_metadata").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE indexes
+SET metadata = $1, updated_at = (STRFTIME('%s', 'now'))
+WHERE index_id = $2
+            "#,
+        )
+        .bind(metadata)
+        .bind(index_id);
+
+        self.execute(update_stmt).await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_index_metadata").await?;
+
+        Ok(rec.into())
+    }
+
+    async fn update_index_elasticsearch(
+        &mut self,
+        index_id: EntityId,
+        es_index: &str,
+        es_alias: &str,
+    ) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT update_index_elasticsearch").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE indexes
+SET es_index = $1, es_alias = $2, updated_at = (STRFTIME('%s', 'now'))
+WHERE index_id = $3
+            "#,
+        )
+        .bind(es_index)
+        .bind(es_alias)
+        .bind(index_id);
+
+        self.execute(update_stmt).await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_index_elasticsearch").await?;
+
+        Ok(rec.into())
+    }
+
+    async fn update_index_progress(
+        &mut self,
+        index_id: EntityId,
+        progress: &str,
+    ) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT update_index_progress").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE indexes
+SET progress = $1, updated_at = (STRFTIME('%s', 'now'))
+WHERE index_id = $2
+            "#,
+        )
+        .bind(progress)
+        .bind(index_id);
+
+        self.execute(update_stmt).await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_index_progress").await?;
+
+        Ok(rec.into())
+    }
+
+    async fn update_index_provenance(
+        &mut self,
+        index_id: EntityId,
+        provenance: &str,
+    ) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT update_index_provenance").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE indexes
+SET provenance = $1, updated_at = (STRFTIME('%s', 'now'))
+WHERE index_id = $2
+            "#,
+        )
+        .bind(provenance)
+        .bind(index_id);
+
+        self.execute(update_stmt).await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_index_provenance").await?;
+
+        Ok(rec.into())
+    }
+
     async fn get_all_indexes(&mut self) -> Result<Vec<IndexEntity>, ProvideError> {
         let recs: Vec<SqliteIndexEntity> = sqlx::query_as(
             r#"
@@ -166,6 +524,495 @@ SELECT * FROM indexes WHERE index_id = $1
 
         Ok(entities)
     }
+
+    async fn get_index(&mut self, index_id: EntityId) -> ProvideResult<IndexEntity> {
+        let rec: SqliteIndexEntity = sqlx::query_as(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id)
+        .fetch_one(self)
+        .await?;
+
+        Ok(rec.into())
+    }
+
+    async fn find_indexes(&mut self, query: &IndexQuery) -> ProvideResult<Vec<IndexEntity>> {
+        let (conditions, values) = index_conditions(query, true);
+        let column = sort_column(query.sort);
+        let direction = if query.descending { "DESC" } else { "ASC" };
+        let mut sql = format!(
+            "SELECT * FROM indexes {} ORDER BY {} {}, index_id {}",
+            conditions, column, direction, direction
+        );
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let mut select = sqlx::query_as::<Sqlite, SqliteIndexEntity>(&sql);
+        for value in values {
+            select = match value {
+                SqlValue::Text(value) => select.bind(value),
+                SqlValue::Integer(value) => select.bind(value),
+            };
+        }
+        let recs = select.fetch_all(self).await?;
+
+        Ok(recs.into_iter().map(IndexEntity::from).collect())
+    }
+
+    async fn count_indexes(&mut self, query: &IndexQuery) -> ProvideResult<i64> {
+        let (conditions, values) = index_conditions(query, false);
+        let sql = format!("SELECT COUNT(*) AS count FROM indexes {}", conditions);
+
+        let mut select = sqlx::query_as::<Sqlite, SqliteCount>(&sql);
+        for value in values {
+            select = match value {
+                SqlValue::Text(value) => select.bind(value),
+                SqlValue::Integer(value) => select.bind(value),
+            };
+        }
+        let rec = select.fetch_one(self).await?;
+
+        Ok(rec.count)
+    }
+
+    async fn delete_index(&mut self, index_id: EntityId) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT delete_index").await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        // Foreign keys are not enforced, so we don't rely on the cascades.
+        let statements = [
+            "DELETE FROM index_events WHERE index_id = $1",
+            "DELETE FROM idempotency_keys WHERE index_id = $1",
+            "DELETE FROM index_dependencies WHERE index_id = $1 OR dependency_id = $1",
+            "DELETE FROM indexes WHERE index_id = $1",
+        ];
+        for statement in statements.iter() {
+            self.execute(sqlx::query(statement).bind(index_id)).await?;
+        }
+
+        self.execute("RELEASE delete_index").await?;
+
+        Ok(rec.into())
+    }
+
+    async fn update_index_active(
+        &mut self,
+        index_id: EntityId,
+        active: bool,
+    ) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT update_index_active").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE indexes
+SET active = $1
+WHERE index_id = $2
+            "#,
+        )
+        .bind(active)
+        .bind(index_id);
+
+        if let Err(err) = self.execute(update_stmt).await {
+            // Another index is active: we leave things as they were.
+            self.execute("ROLLBACK TO update_index_active").await?;
+            self.execute("RELEASE update_index_active").await?;
+            return Err(err.into());
+        }
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_index_active").await?;
+
+        Ok(rec.into())
+    }
+
+    async fn get_active_index(
+        &mut self,
+        index_type: &str,
+        data_source: &str,
+        region: &str,
+    ) -> ProvideResult<Option<IndexEntity>> {
+        let rec: Option<SqliteIndexEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM indexes
+WHERE index_type = $1 AND data_source = $2 AND region = $3 AND active = 1
+            "#,
+        )
+        .bind(index_type)
+        .bind(data_source)
+        .bind(region)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(rec.map(IndexEntity::from))
+    }
+
+    async fn reset_active_indexes(&mut self) -> ProvideResult<()> {
+        self.execute("UPDATE indexes SET active = 0 WHERE active = 1")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_latest_index(
+        &mut self,
+        index_type: &str,
+        region: &str,
+    ) -> ProvideResult<Option<IndexEntity>> {
+        let rec: Option<SqliteIndexEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM indexes
+WHERE index_type = $1 AND region = $2
+ORDER BY created_at DESC, index_id DESC
+LIMIT 1
+            "#,
+        )
+        .bind(index_type)
+        .bind(region)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(rec.map(IndexEntity::from))
+    }
+
+    async fn create_index_dependency(
+        &mut self,
+        index_id: EntityId,
+        dependency_id: EntityId,
+    ) -> ProvideResult<IndexDependencyEntity> {
+        let rec: SqliteIndexDependencyEntity = sqlx::query_as(
+            r#"
+INSERT INTO index_dependencies ( index_id, dependency_id )
+VALUES ( $1, $2 );
+SELECT * FROM index_dependencies WHERE rowid = last_insert_rowid();
+            "#,
+        )
+        .bind(index_id)
+        .bind(dependency_id)
+        .fetch_one(self)
+        .await?;
+
+        Ok(rec.into())
+    }
+
+    async fn get_index_dependencies(
+        &mut self,
+        index_id: EntityId,
+    ) -> ProvideResult<Vec<IndexEntity>> {
+        let recs: Vec<SqliteIndexEntity> = sqlx::query_as(
+            r#"
+SELECT indexes.* FROM indexes
+JOIN index_dependencies ON index_dependencies.dependency_id = indexes.index_id
+WHERE index_dependencies.index_id = $1
+            "#,
+        )
+        .bind(index_id)
+        .fetch_all(self)
+        .await?;
+
+        Ok(recs.into_iter().map(IndexEntity::from).collect())
+    }
+
+    async fn get_all_index_dependencies(&mut self) -> ProvideResult<Vec<IndexDependencyEntity>> {
+        let recs: Vec<SqliteIndexDependencyEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM index_dependencies
+            "#,
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(recs.into_iter().map(IndexDependencyEntity::from).collect())
+    }
+
+    async fn get_index_dependents(
+        &mut self,
+        index_id: EntityId,
+    ) -> ProvideResult<Vec<IndexEntity>> {
+        let recs: Vec<SqliteIndexEntity> = sqlx::query_as(
+            r#"
+SELECT indexes.* FROM indexes
+JOIN index_dependencies ON index_dependencies.index_id = indexes.index_id
+WHERE index_dependencies.dependency_id = $1
+            "#,
+        )
+        .bind(index_id)
+        .fetch_all(self)
+        .await?;
+
+        Ok(recs.into_iter().map(IndexEntity::from).collect())
+    }
+
+    async fn get_index_dependencies_of(
+        &mut self,
+        index_ids: &[EntityId],
+    ) -> ProvideResult<Vec<IndexDependencyEntity>> {
+        if index_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT * FROM index_dependencies WHERE index_id IN ({})",
+            placeholders(index_ids.len())
+        );
+        let mut select = sqlx::query_as::<Sqlite, SqliteIndexDependencyEntity>(&sql);
+        for index_id in index_ids {
+            select = select.bind(*index_id);
+        }
+        let recs = select.fetch_all(self).await?;
+
+        Ok(recs.into_iter().map(IndexDependencyEntity::from).collect())
+    }
+
+    async fn create_index_event(
+        &mut self,
+        index_id: EntityId,
+        status: &str,
+    ) -> ProvideResult<IndexEventEntity> {
+        let rec: SqliteIndexEventEntity = sqlx::query_as(
+            r#"
+INSERT INTO index_events ( index_id, status )
+VALUES ( $1, $2 );
+SELECT * FROM index_events WHERE event_id = last_insert_rowid();
+            "#,
+        )
+        .bind(index_id)
+        .bind(status)
+        .fetch_one(self)
+        .await?;
+
+        Ok(rec.into())
+    }
+
+    async fn get_index_events(
+        &mut self,
+        index_id: EntityId,
+    ) -> ProvideResult<Vec<IndexEventEntity>> {
+        let recs: Vec<SqliteIndexEventEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM index_events WHERE index_id = $1 ORDER BY event_id
+            "#,
+        )
+        .bind(index_id)
+        .fetch_all(self)
+        .await?;
+
+        Ok(recs.into_iter().map(IndexEventEntity::from).collect())
+    }
+
+    async fn get_all_index_events(&mut self) -> ProvideResult<Vec<IndexEventEntity>> {
+        let recs: Vec<SqliteIndexEventEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM index_events ORDER BY index_id, event_id
+            "#,
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(recs.into_iter().map(IndexEventEntity::from).collect())
+    }
+
+    async fn get_index_events_of(
+        &mut self,
+        index_ids: &[EntityId],
+    ) -> ProvideResult<Vec<IndexEventEntity>> {
+        if index_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT * FROM index_events WHERE index_id IN ({}) ORDER BY index_id, event_id",
+            placeholders(index_ids.len())
+        );
+        let mut select = sqlx::query_as::<Sqlite, SqliteIndexEventEntity>(&sql);
+        for index_id in index_ids {
+            select = select.bind(*index_id);
+        }
+        let recs = select.fetch_all(self).await?;
+
+        Ok(recs.into_iter().map(IndexEventEntity::from).collect())
+    }
+
+    async fn create_idempotency_key(
+        &mut self,
+        idempotency_key: &str,
+        index_id: EntityId,
+        request: &str,
+    ) -> ProvideResult<IdempotencyKeyEntity> {
+        let rec: SqliteIdempotencyKeyEntity = sqlx::query_as(
+            r#"
+INSERT INTO idempotency_keys ( idempotency_key, index_id, request )
+VALUES ( $1, $2, $3 );
+SELECT * FROM idempotency_keys WHERE idempotency_key = $1;
+            "#,
+        )
+        .bind(idempotency_key)
+        .bind(index_id)
+        .bind(request)
+        .fetch_one(self)
+        .await?;
+
+        Ok(rec.into())
+    }
+
+    async fn get_idempotency_key(
+        &mut self,
+        idempotency_key: &str,
+    ) -> ProvideResult<Option<IdempotencyKeyEntity>> {
+        let rec: Option<SqliteIdempotencyKeyEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM idempotency_keys WHERE idempotency_key = $1
+            "#,
+        )
+        .bind(idempotency_key)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(rec.map(IdempotencyKeyEntity::from))
+    }
+
+    async fn delete_idempotency_keys(
+        &mut self,
+        created_before: DateTime<Utc>,
+    ) -> ProvideResult<()> {
+        self.execute(
+            sqlx::query("DELETE FROM idempotency_keys WHERE created_at < $1")
+                .bind(created_before.timestamp()),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn create_bundle(&mut self, region: &str, profile: &str) -> ProvideResult<BundleEntity> {
+        let rec: SqliteBundleEntity = sqlx::query_as(
+            r#"
+INSERT INTO bundles ( region, profile )
+VALUES ( $1, $2 );
+SELECT * FROM bundles WHERE bundle_id = last_insert_rowid();
+            "#,
+        )
+        .bind(region)
+        .bind(profile)
+        .fetch_one(self)
+        .await?;
+
+        Ok(rec.into())
+    }
+
+    async fn get_all_bundles(&mut self) -> ProvideResult<Vec<BundleEntity>> {
+        let recs: Vec<SqliteBundleEntity> = sqlx::query_as(
+            r#"
+SELECT * FROM bundles ORDER BY updated_at
+            "#,
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(recs.into_iter().map(BundleEntity::from).collect())
+    }
+
+    async fn update_bundle_status(
+        &mut self,
+        bundle_id: EntityId,
+        status: &str,
+    ) -> ProvideResult<BundleEntity> {
+        self.execute("SAVEPOINT update_bundle_status").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE bundles
+SET status = $1, updated_at = (STRFTIME('%s', 'now'))
+WHERE bundle_id = $2
+            "#,
+        )
+        .bind(status)
+        .bind(bundle_id);
+
+        self.execute(update_stmt).await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM bundles WHERE bundle_id = $1
+            "#,
+        )
+        .bind(bundle_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteBundleEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_bundle_status").await?;
+
+        Ok(rec.into())
+    }
+
+    async fn update_index_bundle(
+        &mut self,
+        index_id: EntityId,
+        bundle_id: EntityId,
+    ) -> ProvideResult<IndexEntity> {
+        self.execute("SAVEPOINT update_index_bundle").await?;
+
+        let update_stmt = sqlx::query(
+            r#"
+UPDATE indexes
+SET bundle_id = $1
+WHERE index_id = $2
+            "#,
+        )
+        .bind(bundle_id)
+        .bind(index_id);
+
+        self.execute(update_stmt).await?;
+
+        let select_stmt = sqlx::query(
+            r#"
+SELECT * FROM indexes WHERE index_id = $1
+            "#,
+        )
+        .bind(index_id);
+
+        let rec = self
+            .fetch(select_stmt)
+            .next()
+            .await?
+            .map(|row| SqliteIndexEntity::from_row(&row).expect("invalid entity"))
+            .ok_or(ProvideError::NotFound)?;
+
+        self.execute("RELEASE update_index_bundle").await?;
+
+        Ok(rec.into())
+    }
 }
 
 pub async fn init_db(conn_str: &str, logger: Logger) -> Result<(), error::Error> {
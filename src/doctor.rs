@@ -0,0 +1,45 @@
+use clap::ArgMatches;
+use slog::{info, warn, Logger};
+
+use mimir_ingest::error;
+use mimir_ingest::fsm::Registry;
+use mimir_ingest::settings::Settings;
+
+// Check the tools needed by each data source, and tell which data sources can be used.
+#[allow(clippy::needless_lifetimes)]
+pub async fn doctor<'a>(matches: &ArgMatches<'a>, logger: Logger) -> Result<(), error::Error> {
+    info!(logger, "Checking tools");
+    let settings = Settings::new(matches)?;
+
+    // The registry checks the tools as it is created, and logs what it finds.
+    let registry = Registry::new(&settings, &logger);
+
+    let mut unavailable = Vec::new();
+    for name in registry.names() {
+        let source = registry.get(name).expect("registered data source");
+        let missing = registry.missing_tools(source.as_ref());
+        if missing.is_empty() {
+            info!(logger, "Data source {} is available", name);
+        } else {
+            let tools = missing
+                .iter()
+                .map(|check| check.name.as_str())
+                .collect::<Vec<_>>();
+            warn!(
+                logger,
+                "Data source {} is not available, missing {}",
+                name,
+                tools.join(", ")
+            );
+            unavailable.push(name);
+        }
+    }
+
+    if unavailable.is_empty() {
+        Ok(())
+    } else {
+        Err(error::Error::MiscError {
+            details: format!("Unavailable data sources: {}", unavailable.join(", ")),
+        })
+    }
+}
@@ -1,4 +1,4 @@
-use juniper::{graphql_value, FieldError, IntoFieldError};
+use juniper::{graphql_value, FieldError, IntoFieldError, Object, Value};
 use snafu::{Backtrace, Snafu};
 use std::io;
 
@@ -10,6 +10,21 @@ pub enum Error {
     #[snafu(visibility(pub))]
     MiscError { details: String },
 
+    #[snafu(display("Timeout Error: {}", details))]
+    #[snafu(visibility(pub))]
+    TimeoutError { details: String },
+
+    #[snafu(display("Conflict Error: {}", details))]
+    #[snafu(visibility(pub))]
+    ConflictError { details: String },
+
+    #[snafu(display("Validation Error: {}", details))]
+    #[snafu(visibility(pub))]
+    ValidationError {
+        details: String,
+        violations: Vec<Violation>,
+    },
+
     #[snafu(display("Config Error: {} => {}", details, source))]
     #[snafu(visibility(pub))]
     ConfigError {
@@ -113,6 +128,10 @@ pub enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("CSV Error: {} => {}", details, source))]
+    #[snafu(visibility(pub))]
+    CSVError { details: String, source: csv::Error },
+
     #[snafu(display("DB Provider Error: {} => {}", details, source))]
     #[snafu(visibility(pub))]
     DBProvideError {
@@ -129,6 +148,80 @@ pub enum Error {
     },
 }
 
+// A field of a request which is refused, and why.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub code: &'static str, // eg 'UNKNOWN_REGION'
+    pub path: Vec<String>,  // Where the field is in the arguments, eg ['index', 'region']
+    pub message: String,
+}
+
+impl Violation {
+    fn to_value(&self) -> Value {
+        // Positions in lists are numbers, as in the paths of GraphQL errors.
+        let path = self
+            .path
+            .iter()
+            .map(|segment| match segment.parse::<i32>() {
+                Ok(position) => Value::scalar(position),
+                Err(_) => Value::scalar(segment.clone()),
+            })
+            .collect();
+        let mut object = Object::with_capacity(3);
+        object.add_field("code", Value::scalar(String::from(self.code)));
+        object.add_field("path", Value::list(path));
+        object.add_field("message", Value::scalar(self.message.clone()));
+        Value::object(object)
+    }
+}
+
+impl Error {
+    // A request refused for the given reasons.
+    pub fn validation(violations: Vec<Violation>) -> Self {
+        let details = violations
+            .iter()
+            .map(|violation| format!("{}: {}", violation.path.join("."), violation.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Error::ValidationError {
+            details,
+            violations,
+        }
+    }
+
+    // The error of a part of the arguments (eg one of a list of requests), seen from all the
+    // arguments: the paths of the violations start with the path of the part.
+    pub fn within(self, prefix: &[&str]) -> Self {
+        match self {
+            // The details are made again, with the new paths.
+            Error::ValidationError { violations, .. } => Error::validation(
+                violations
+                    .into_iter()
+                    .map(|violation| Violation {
+                        path: prefix
+                            .iter()
+                            .map(|segment| String::from(*segment))
+                            .chain(violation.path)
+                            .collect(),
+                        ..violation
+                    })
+                    .collect(),
+            ),
+            err => err,
+        }
+    }
+
+    // A code telling clients what went wrong, without parsing messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ValidationError { .. } => "VALIDATION_ERROR",
+            Error::ConflictError { .. } => "CONFLICT",
+            Error::TimeoutError { .. } => "TIMEOUT",
+            _ => "INTERNAL_ERROR",
+        }
+    }
+}
+
 impl IntoFieldError for Error {
     fn into_field_error(self) -> FieldError {
         match self {
@@ -136,6 +229,33 @@ impl IntoFieldError for Error {
                 let errmsg = format!("{}", err);
                 FieldError::new("User Error", graphql_value!({ "internal_error": errmsg }))
             }
+            err @ Error::TimeoutError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new(
+                    "Timeout Error",
+                    graphql_value!({ "internal_error": errmsg }),
+                )
+            }
+            err @ Error::ConflictError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new(
+                    "Conflict Error",
+                    graphql_value!({ "code": "CONFLICT", "internal_error": errmsg }),
+                )
+            }
+            Error::ValidationError {
+                details,
+                violations,
+            } => {
+                let mut extensions = Object::with_capacity(3);
+                extensions.add_field("code", Value::scalar(String::from("VALIDATION_ERROR")));
+                extensions.add_field("details", Value::scalar(details));
+                extensions.add_field(
+                    "violations",
+                    Value::list(violations.iter().map(Violation::to_value).collect()),
+                );
+                FieldError::new("Validation Error", Value::object(extensions))
+            }
             err @ Error::ConfigError { .. } => {
                 let errmsg = format!("{}", err);
                 FieldError::new(
@@ -173,6 +293,11 @@ impl IntoFieldError for Error {
                 FieldError::new("Serde Error", graphql_value!({ "internal_error": errmsg }))
             }
 
+            err @ Error::CSVError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new("CSV Error", graphql_value!({ "internal_error": errmsg }))
+            }
+
             err @ Error::DBProvideError { .. } => {
                 let errmsg = format!("{}", err);
                 FieldError::new(
@@ -0,0 +1,337 @@
+use slog::{info, warn, Logger};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+use super::error;
+
+// Why a download failed, told to all the jobs waiting for it.
+#[derive(Clone)]
+struct Failure {
+    details: String,
+    timed_out: bool,
+}
+
+// The outcome of a download, once it is known.
+type Outcome = Option<Result<PathBuf, Failure>>;
+
+// A downloaded file (or directory), and how many jobs are using it.
+struct Artifact {
+    refs: usize,
+    outcome: watch::Receiver<Outcome>,
+}
+
+// The artifacts downloaded by the FSMs, by key (eg 'osm/<region>'), shared by all the jobs.
+// Concurrent jobs needing the same artifact wait on a single in-flight download. Each job holds
+// a lease on the artifact, and, if cleanup is enabled, the file is removed once the last lease
+// is dropped, never before.
+#[derive(Clone)]
+pub struct Artifacts {
+    artifacts: Arc<Mutex<HashMap<String, Artifact>>>,
+    cleanup: bool,
+    logger: Logger,
+}
+
+impl Artifacts {
+    pub fn new(cleanup: bool, logger: Logger) -> Self {
+        Artifacts {
+            artifacts: Arc::new(Mutex::new(HashMap::new())),
+            cleanup,
+            logger,
+        }
+    }
+
+    // Get a lease on the artifact 'key'. If no other job is using it, we download it by calling
+    // 'download' on the blocking pool, otherwise we wait for the other job's download to complete.
+    pub async fn fetch<F>(&self, key: &str, download: F) -> Result<Lease, error::Error>
+    where
+        F: FnOnce() -> Result<PathBuf, error::Error> + Send + 'static,
+    {
+        let (mut outcome, sender) = {
+            let mut artifacts = self.artifacts.lock().unwrap();
+            match artifacts.get_mut(key) {
+                Some(artifact) => {
+                    artifact.refs += 1;
+                    (artifact.outcome.clone(), None)
+                }
+                None => {
+                    let (sender, outcome) = watch::channel(None);
+                    artifacts.insert(
+                        String::from(key),
+                        Artifact {
+                            refs: 1,
+                            outcome: outcome.clone(),
+                        },
+                    );
+                    (outcome, Some(sender))
+                }
+            }
+        };
+
+        match sender {
+            Some(sender) => {
+                info!(self.logger, "Downloading artifact {}", key);
+                // The download blocks, so it runs on the blocking pool, not on the runtime.
+                let res = match tokio::task::spawn_blocking(download).await {
+                    Ok(res) => res.map_err(|err| Failure {
+                        timed_out: matches!(err, error::Error::TimeoutError { .. }),
+                        details: format!("{}", err),
+                    }),
+                    Err(err) => Err(Failure {
+                        details: format!("{}", err),
+                        timed_out: false,
+                    }),
+                };
+                let _ = sender.broadcast(Some(res));
+            }
+            None => {
+                info!(self.logger, "Waiting for artifact {}", key);
+            }
+        }
+
+        let res = loop {
+            let current = outcome.borrow().clone();
+            if let Some(res) = current {
+                break res;
+            }
+            if outcome.recv().await.is_none() {
+                break Err(Failure {
+                    details: String::from("The download was abandoned"),
+                    timed_out: false,
+                });
+            }
+        };
+
+        match res {
+            Ok(path) => Ok(Lease {
+                artifacts: self.clone(),
+                key: String::from(key),
+                path,
+            }),
+            Err(failure) => {
+                self.release(key, None);
+                let details = format!("Could not download {}: {}", key, failure.details);
+                if failure.timed_out {
+                    Err(error::Error::TimeoutError { details })
+                } else {
+                    Err(error::Error::MiscError { details })
+                }
+            }
+        }
+    }
+
+    // Whether a job is using the file (or directory) at 'path', or still downloading an
+    // artifact, which may be it.
+    pub fn in_use(&self, path: &Path) -> bool {
+        self.artifacts
+            .lock()
+            .unwrap()
+            .values()
+            .any(|artifact| match &*artifact.outcome.borrow() {
+                Some(Ok(artifact)) => artifact == path,
+                Some(Err(_)) => false,
+                None => true,
+            })
+    }
+
+    fn acquire(&self, key: &str) {
+        if let Some(artifact) = self.artifacts.lock().unwrap().get_mut(key) {
+            artifact.refs += 1;
+        }
+    }
+
+    // Drop a reference on the artifact, and forget it once no job is using it anymore.
+    fn release(&self, key: &str, path: Option<&Path>) {
+        let mut artifacts = self.artifacts.lock().unwrap();
+        let unused = match artifacts.get_mut(key) {
+            Some(artifact) => {
+                artifact.refs -= 1;
+                artifact.refs == 0
+            }
+            None => false,
+        };
+        if !unused {
+            return;
+        }
+        artifacts.remove(key);
+        if let (true, Some(path)) = (self.cleanup, path) {
+            info!(self.logger, "Removing unused artifact {}", path.display());
+            let res = if path.is_dir() {
+                std::fs::remove_dir_all(path)
+            } else {
+                std::fs::remove_file(path)
+            };
+            if let Err(err) = res {
+                warn!(
+                    self.logger,
+                    "Could not remove artifact {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+// A job's hold on an artifact. The artifact is released when the lease is dropped.
+pub struct Lease {
+    artifacts: Artifacts,
+    key: String,
+    path: PathBuf,
+}
+
+impl Lease {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Clone for Lease {
+    fn clone(&self) -> Self {
+        self.artifacts.acquire(&self.key);
+        Lease {
+            artifacts: self.artifacts.clone(),
+            key: self.key.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.artifacts.release(&self.key, Some(&self.path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{o, Discard};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn artifact(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "mimir_ingest_artifact_{}_{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    // A download writing the file at 'path', counting how many times it runs.
+    fn download(
+        path: &Path,
+        downloads: &Arc<AtomicUsize>,
+    ) -> impl FnOnce() -> Result<PathBuf, error::Error> + Send + 'static {
+        let path = path.to_path_buf();
+        let downloads = downloads.clone();
+        move || {
+            downloads.fetch_add(1, Ordering::SeqCst);
+            fs::write(&path, "").unwrap();
+            Ok(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_jobs_share_a_single_download() {
+        let artifacts = Artifacts::new(true, Logger::root(Discard, o!()));
+        let path = artifact("shared");
+        let downloads = Arc::new(AtomicUsize::new(0));
+
+        let (first, second) = tokio::join!(
+            artifacts.fetch("osm/shared", download(&path, &downloads)),
+            artifacts.fetch("osm/shared", download(&path, &downloads)),
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        assert_eq!(first.path(), path.as_path());
+        assert_eq!(second.path(), path.as_path());
+
+        drop(first);
+        assert!(artifacts.in_use(&path));
+        assert!(path.exists());
+
+        drop(second);
+        assert!(!artifacts.in_use(&path));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn cloned_leases_hold_the_artifact() {
+        let artifacts = Artifacts::new(true, Logger::root(Discard, o!()));
+        let path = artifact("cloned");
+        let downloads = Arc::new(AtomicUsize::new(0));
+
+        let lease = artifacts
+            .fetch("osm/cloned", download(&path, &downloads))
+            .await
+            .unwrap();
+        let clone = lease.clone();
+
+        drop(lease);
+        assert!(artifacts.in_use(&path));
+        assert!(path.exists());
+
+        drop(clone);
+        assert!(!artifacts.in_use(&path));
+        assert!(!path.exists());
+
+        // Once released, the artifact is downloaded again.
+        let lease = artifacts
+            .fetch("osm/cloned", download(&path, &downloads))
+            .await
+            .unwrap();
+        assert_eq!(downloads.load(Ordering::SeqCst), 2);
+        drop(lease);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn artifacts_are_kept_without_cleanup() {
+        let artifacts = Artifacts::new(false, Logger::root(Discard, o!()));
+        let path = artifact("kept");
+        let downloads = Arc::new(AtomicUsize::new(0));
+
+        let lease = artifacts
+            .fetch("osm/kept", download(&path, &downloads))
+            .await
+            .unwrap();
+        drop(lease);
+        assert!(!artifacts.in_use(&path));
+        assert!(path.exists());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_are_released() {
+        let artifacts = Artifacts::new(true, Logger::root(Discard, o!()));
+        let path = artifact("failed");
+
+        let res = artifacts
+            .fetch("osm/failed", || {
+                Err(error::Error::MiscError {
+                    details: String::from("unreachable"),
+                })
+            })
+            .await;
+        assert!(res.is_err());
+        assert!(!artifacts.in_use(&path));
+        assert!(artifacts.artifacts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn timed_out_downloads_time_out() {
+        let artifacts = Artifacts::new(true, Logger::root(Discard, o!()));
+
+        let res = artifacts
+            .fetch("osm/slow", || {
+                Err(error::Error::TimeoutError {
+                    details: String::from("too slow"),
+                })
+            })
+            .await;
+        assert!(matches!(res, Err(error::Error::TimeoutError { .. })));
+    }
+}
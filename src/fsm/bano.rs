@@ -1,58 +1,389 @@
+use async_trait::async_trait;
 use snafu::ResultExt;
 use std::path::PathBuf;
-use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use url::Url;
 
 use super::download;
 use super::error;
+use super::logs::Log;
+use super::options::{Options, CONFIG_DIR, DATASET, REPLICAS, SHARDS, THREADS};
+use super::tools::BANO2MIMIR;
+use super::{DataSource, Job, Progress, Reporter};
 
-pub fn index_bano_region(
+pub async fn index_bano_region(
     mimirs_dir: PathBuf,
     es: Url,
     filepath: PathBuf,
+    options: &Options,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
 ) -> Result<(), error::Error> {
     let mut execpath = mimirs_dir;
     // execpath.push("target");
     // execpath.push("release");
-    execpath.push("bano2mimir");
-    // FIXME Need test file exists
-    let mut command = Command::new(&execpath);
+    execpath.push(BANO2MIMIR);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
     command
         .arg("--connection-string")
         .arg(es.as_str())
         .arg("--input")
-        .arg(filepath);
-    let output = command.output().context(error::IOError {
-        details: format!(
-            "Could not create bano2mimir command using {}",
-            execpath.display()
-        ),
-    })?;
-    if !output.status.success() {
-        Err(error::Error::MiscError {
-            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
-        })
-    } else {
-        Ok(())
-    }
-}
-
-pub fn download_bano_region(working_dir: PathBuf, region: &str) -> Result<PathBuf, error::Error> {
-    let filename = match region.len() {
-        1 => format!("bano-0{}.csv", region),
-        _ => format!("bano-{}.csv", region),
+        .arg(filepath)
+        .args(options.args());
+    log.run(&mut command, BANO2MIMIR, reporter).await
+}
+
+// The departments making up a group of regions, eg 'france'.
+// Returns None if the region is not a group, but a single department.
+pub fn departments(region: &str) -> Option<Vec<String>> {
+    match region {
+        "france" => {
+            let mut departments = (1..=95)
+                .filter(|d| *d != 20) // Corsica is split in 2A and 2B
+                .map(|d| format!("{:02}", d))
+                .collect::<Vec<_>>();
+            departments.extend(
+                ["2A", "2B", "971", "972", "973", "974", "976"]
+                    .iter()
+                    .map(|d| String::from(*d)),
+            );
+            departments.sort();
+            Some(departments)
+        }
+        _ => None,
+    }
+}
+
+// Whether the region is a group of departments, or one of them (eg '1', '01' or '2A').
+pub fn is_bano_region(region: &str) -> bool {
+    let department = match region.len() {
+        1 => format!("0{}", region),
+        _ => String::from(region),
     };
-    let target = format!("http://bano.openstreetmap.fr/data/{}", filename);
+    departments(region).is_some()
+        || departments("france")
+            .unwrap_or_default()
+            .contains(&department)
+}
+
+pub fn download_bano_region(
+    working_dir: PathBuf,
+    region: &str,
+    timeout: Option<Duration>,
+) -> Result<PathBuf, error::Error> {
     let mut filepath = working_dir;
     filepath.push("bano");
+    download_bano_department(filepath, region, timeout)
+}
+
+// The OSM extract (see osm) which contains a department, eg 'ile-de-france' for '75', where
+// its admins come from. None for a group of departments, which no extract matches.
+pub fn osm_region(region: &str) -> Option<&'static str> {
+    let department = match region.len() {
+        1 => format!("0{}", region),
+        _ => String::from(region),
+    };
+    let osm_region = match department.as_str() {
+        "67" | "68" => "alsace",
+        "24" | "33" | "40" | "47" | "64" => "aquitaine",
+        "03" | "15" | "43" | "63" => "auvergne",
+        "14" | "50" | "61" => "basse-normandie",
+        "21" | "58" | "71" | "89" => "bourgogne",
+        "22" | "29" | "35" | "56" => "bretagne",
+        "18" | "28" | "36" | "37" | "41" | "45" => "centre",
+        "08" | "10" | "51" | "52" => "champagne-ardenne",
+        "2A" | "2B" => "corse",
+        "25" | "39" | "70" | "90" => "franche-comte",
+        "27" | "76" => "haute-normandie",
+        "75" | "77" | "78" | "91" | "92" | "93" | "94" | "95" => "ile-de-france",
+        "11" | "30" | "34" | "48" | "66" => "languedoc-roussillon",
+        "19" | "23" | "87" => "limousin",
+        "54" | "55" | "57" | "88" => "lorraine",
+        "09" | "12" | "31" | "32" | "46" | "65" | "81" | "82" => "midi-pyrenees",
+        "59" | "62" => "nord-pas-de-calais",
+        "44" | "49" | "53" | "72" | "85" => "pays-de-la-loire",
+        "02" | "60" | "80" => "picardie",
+        "16" | "17" | "79" | "86" => "poitou-charentes",
+        "04" | "05" | "06" | "13" | "83" | "84" => "provence-alpes-cote-d-azur",
+        "01" | "07" | "26" | "38" | "42" | "69" | "73" | "74" => "rhone-alpes",
+        "971" => "guadeloupe",
+        "972" => "martinique",
+        "973" => "guyane",
+        "974" => "reunion",
+        "976" => "mayotte",
+        _ => return None,
+    };
+    Some(osm_region)
+}
+
+// Download the BANO file of a single department in the directory 'filepath'
+fn download_bano_department(
+    filepath: PathBuf,
+    department: &str,
+    timeout: Option<Duration>,
+) -> Result<PathBuf, error::Error> {
+    let filename = match department.len() {
+        1 => format!("bano-0{}.csv", department),
+        _ => format!("bano-{}.csv", department),
+    };
+    let target = format!("http://bano.openstreetmap.fr/data/{}", filename);
     if !filepath.is_dir() {
-        std::fs::create_dir(filepath.as_path()).context(error::IOError {
+        std::fs::create_dir_all(filepath.as_path()).context(error::IOError {
             details: format!(
                 "Expected to download in BANO file in {}, which is not a directory",
                 filepath.display()
             ),
         })?;
     }
-    let res = download::download(&target, filepath)?;
+    let res = download::download(&target, filepath, timeout)?;
     Ok(res.0)
 }
+
+// Download the BANO files of all the departments of a group in the directory 'bano/<region>',
+// using 'concurrency' threads, each download giving up after 'timeout'. bano2mimir can then
+// index the whole directory at once.
+// The outcome of each department is sent on the returned channel as soon as it is known, so
+// that it can be awaited without holding up the runtime.
+pub fn download_bano_group(
+    working_dir: PathBuf,
+    region: &str,
+    departments: Vec<String>,
+    concurrency: usize,
+    timeout: Option<Duration>,
+) -> (
+    PathBuf,
+    UnboundedReceiver<(String, Result<PathBuf, error::Error>)>,
+) {
+    let mut filepath = working_dir;
+    filepath.push("bano");
+    filepath.push(region);
+    let queue = Arc::new(Mutex::new(departments));
+    let (sender, receiver) = unbounded_channel();
+    for _ in 0..concurrency.max(1) {
+        let queue = queue.clone();
+        let sender = sender.clone();
+        let filepath = filepath.clone();
+        thread::spawn(move || loop {
+            let department = match queue.lock().unwrap().pop() {
+                Some(department) => department,
+                None => break,
+            };
+            let res = download_bano_department(filepath.clone(), &department, timeout);
+            if sender.send((department, res)).is_err() {
+                break;
+            }
+        });
+    }
+    (filepath, receiver)
+}
+
+// Addresses from BANO, indexed with bano2mimir
+pub struct Bano {
+    pub concurrency: usize, // How many BANO files we download at the same time
+}
+
+#[async_trait]
+impl DataSource for Bano {
+    fn name(&self) -> &'static str {
+        "bano"
+    }
+
+    fn index_types(&self) -> &'static [&'static str] {
+        &["addresses"]
+    }
+
+    fn tools(&self) -> &'static [&'static str] {
+        &[BANO2MIMIR]
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &[SHARDS, REPLICAS, THREADS, DATASET, CONFIG_DIR]
+    }
+
+    // The admins of a department come from the OSM extract containing it.
+    fn prerequisites_region(&self, region: &str) -> Option<String> {
+        osm_region(region).map(String::from)
+    }
+
+    // A bundle of an OSM extract gets an index for each of its departments.
+    fn bundle_regions(&self, region: &str) -> Vec<String> {
+        if is_bano_region(region) {
+            return vec![String::from(region)];
+        }
+        departments("france")
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|department| osm_region(department) == Some(region))
+            .collect()
+    }
+
+    async fn check_region(&self, region: &str) -> Result<(), error::Error> {
+        if is_bano_region(region) {
+            Ok(())
+        } else {
+            Err(error::Error::MiscError {
+                details: format!(
+                    "BANO has no region '{}', expected a department or 'france'",
+                    region
+                ),
+            })
+        }
+    }
+
+    // A group of regions (eg 'france') is downloaded department by department, concurrently.
+    // Each time a department is done, we publish our progress. The download fails if any of
+    // the departments fails.
+    async fn download(
+        &self,
+        job: &mut Job,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        let departments = match departments(&job.region) {
+            Some(departments) => departments,
+            None => {
+                // A single department is downloaded on the blocking pool.
+                let working_dir = job.working_dir.clone();
+                let region = job.region.clone();
+                let timeout = job.log.remaining();
+                return tokio::task::spawn_blocking(move || {
+                    download_bano_region(working_dir, &region, timeout)
+                })
+                .await
+                .context(error::TokioJoinError {
+                    details: format!("Could not download BANO for region {}", job.region),
+                })?;
+            }
+        };
+        let total = departments.len() as u64;
+        let (file_path, mut receiver) = download_bano_group(
+            job.working_dir.clone(),
+            &job.region,
+            departments,
+            self.concurrency,
+            job.log.remaining(),
+        );
+        let mut failures = Vec::new();
+        let mut timed_out = false;
+        let mut count = 0;
+        loop {
+            // The channel is closed once all the departments have been downloaded.
+            let (department, res) = match receiver.recv().await {
+                Some(outcome) => outcome,
+                None => break,
+            };
+            count += 1;
+            let phase = match res {
+                Ok(_) => format!("Downloaded BANO for department {}", department),
+                Err(err) => {
+                    timed_out |= matches!(err, error::Error::TimeoutError { .. });
+                    failures.push(format!("{} ({})", department, err));
+                    format!("Could not download BANO for department {}", department)
+                }
+            };
+            reporter
+                .progress(Progress {
+                    phase,
+                    count,
+                    total: Some(total),
+                    percentage: None,
+                })
+                .await;
+        }
+        if failures.is_empty() {
+            return Ok(file_path);
+        }
+        let details = format!(
+            "Could not download BANO for departments {}",
+            failures.join(", ")
+        );
+        if timed_out {
+            // The step timed out, even if other departments failed for other reasons.
+            Err(error::Error::TimeoutError { details })
+        } else {
+            Err(error::Error::MiscError { details })
+        }
+    }
+
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        index_bano_region(
+            job.mimirs_dir.clone(),
+            job.es.clone(),
+            file_path,
+            &job.options,
+            &job.log,
+            reporter,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_departments_of_france() {
+        let departments = departments("france").unwrap();
+        assert_eq!(departments.len(), 101);
+        assert_eq!(departments.first().map(String::as_str), Some("01"));
+        for department in &["2A", "2B", "75", "95", "971", "976"] {
+            assert!(departments.contains(&String::from(*department)));
+        }
+        // Corsica is no longer department 20, and there is no department 975.
+        assert!(!departments.contains(&String::from("20")));
+        assert!(!departments.contains(&String::from("975")));
+    }
+
+    #[test]
+    fn has_no_departments_for_a_single_department() {
+        assert_eq!(departments("75"), None);
+        assert_eq!(departments("ile-de-france"), None);
+    }
+
+    #[test]
+    fn knows_the_regions_of_bano() {
+        for region in &["france", "1", "01", "2A", "75", "974"] {
+            assert!(is_bano_region(region), "'{}' was refused", region);
+        }
+        for region in &["", "20", "96", "2C", "ile-de-france"] {
+            assert!(!is_bano_region(region), "'{}' was accepted", region);
+        }
+    }
+
+    #[test]
+    fn finds_the_osm_region_of_each_department() {
+        for department in departments("france").unwrap() {
+            assert!(
+                osm_region(&department).is_some(),
+                "{} has no region",
+                department
+            );
+        }
+        assert_eq!(osm_region("75"), Some("ile-de-france"));
+        assert_eq!(osm_region("1"), Some("rhone-alpes"));
+        assert_eq!(osm_region("2B"), Some("corse"));
+        assert_eq!(osm_region("974"), Some("reunion"));
+        assert_eq!(osm_region("france"), None);
+    }
+
+    #[test]
+    fn maps_bundle_regions_to_departments() {
+        let bano = Bano { concurrency: 1 };
+        assert_eq!(
+            bano.bundle_regions("ile-de-france"),
+            vec!["75", "77", "78", "91", "92", "93", "94", "95"]
+        );
+        assert_eq!(bano.bundle_regions("corse"), vec!["2A", "2B"]);
+        assert_eq!(bano.bundle_regions("france"), vec!["france"]);
+        assert!(bano.bundle_regions("andorra").is_empty());
+    }
+}
@@ -1,44 +1,44 @@
+use async_trait::async_trait;
 use snafu::ResultExt;
 use std::path::PathBuf;
-use std::process::Command;
 use url::Url;
 
+use super::artifacts::Artifacts;
 use super::error;
+use super::logs::Log;
+use super::options::Options;
+use super::osm;
+use super::tools::{COSMOGONY, COSMOGONY2MIMIR};
+use super::{DataSource, Job, Reporter};
 
-pub fn index_cosmogony_region(
+pub async fn index_cosmogony_region(
     mimirs_dir: PathBuf,
     es: Url,
     filepath: PathBuf,
+    options: &Options,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
 ) -> Result<(), error::Error> {
     let mut execpath = mimirs_dir;
-    execpath.push("cosmogony2mimir");
-    // FIXME Need test file exists
-    let mut command = Command::new(&execpath);
+    execpath.push(COSMOGONY2MIMIR);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
     command
         .arg("--connection-string")
         .arg(es.as_str())
         .arg("--input")
-        .arg(filepath);
-    let output = command.output().context(error::IOError {
-        details: format!(
-            "Could not create cosmogony2mimir command using {}",
-            execpath.display()
-        ),
-    })?;
-    if !output.status.success() {
-        Err(error::Error::MiscError {
-            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
-        })
-    } else {
-        Ok(())
-    }
+        .arg(filepath)
+        .args(options.args());
+    log.run(&mut command, COSMOGONY2MIMIR, reporter).await
 }
 
-pub fn generate_cosmogony(
+pub async fn generate_cosmogony(
     cosmogony_dir: PathBuf,
     working_dir: PathBuf,
     inputpath: PathBuf,
     region: &str,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
 ) -> Result<PathBuf, error::Error> {
     let filename = format!("{}.json.gz", region);
     let mut outputpath = working_dir;
@@ -53,9 +53,9 @@ pub fn generate_cosmogony(
     }
     outputpath.push(&filename);
     let mut execpath = cosmogony_dir;
-    execpath.push("cosmogony");
-    // FIXME Need to test exec exists
-    let mut command = Command::new(&execpath);
+    execpath.push(COSMOGONY);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
     command
         .arg("--country-code")
         .arg("FR")
@@ -63,17 +63,90 @@ pub fn generate_cosmogony(
         .arg(inputpath)
         .arg("--output")
         .arg(outputpath.clone());
-    let output = command.output().context(error::IOError {
-        details: format!(
-            "Could not create cosmogony command using {}",
-            execpath.display()
-        ),
-    })?;
-    if !output.status.success() {
-        Err(error::Error::MiscError {
-            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
-        })
-    } else {
-        Ok(outputpath)
+    log.run(&mut command, COSMOGONY, reporter).await?;
+    Ok(outputpath)
+}
+
+// Admins generated by cosmogony from OpenStreetMap, indexed with cosmogony2mimir
+pub struct Cosmogony {
+    pub artifacts: Artifacts, // Where we get the PBF from, shared with the other jobs
+}
+
+#[async_trait]
+impl DataSource for Cosmogony {
+    fn name(&self) -> &'static str {
+        "cosmogony"
+    }
+
+    fn index_types(&self) -> &'static [&'static str] {
+        &["admins"]
+    }
+
+    fn tools(&self) -> &'static [&'static str] {
+        &[COSMOGONY, COSMOGONY2MIMIR]
+    }
+
+    fn uses_osm_pbf(&self) -> bool {
+        true
+    }
+
+    async fn check_region(&self, region: &str) -> Result<(), error::Error> {
+        osm::check_osm_region(region).await
+    }
+
+    async fn download(
+        &self,
+        job: &mut Job,
+        _reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        let lease = osm::fetch_osm_region(
+            &self.artifacts,
+            job.working_dir.clone(),
+            &job.region,
+            job.log.remaining(),
+        )
+        .await?;
+        let file_path = lease.path().to_path_buf();
+        job.leases.push(lease);
+        Ok(file_path)
+    }
+
+    // The PBF needs to go through cosmogony first
+    fn needs_processing(&self, _job: &Job) -> bool {
+        true
+    }
+
+    async fn process(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        generate_cosmogony(
+            job.cosmogony_dir.clone(),
+            job.working_dir.clone(),
+            file_path,
+            &job.region,
+            &job.log,
+            reporter,
+        )
+        .await
+    }
+
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        index_cosmogony_region(
+            job.mimirs_dir.clone(),
+            job.es.clone(),
+            file_path,
+            &job.options,
+            &job.log,
+            reporter,
+        )
+        .await
     }
 }
@@ -1,11 +1,19 @@
 use super::error;
-use snafu::ResultExt;
+use super::provenance;
+use snafu::{IntoError, ResultExt};
 use std::fs;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
 use url::Url;
 
-pub fn download(link: &str, download_path: PathBuf) -> Result<(PathBuf, usize), error::Error> {
+// Download 'link' in the directory 'download_path', giving up after 'timeout', if any.
+pub fn download(
+    link: &str,
+    download_path: PathBuf,
+    timeout: Option<Duration>,
+) -> Result<(PathBuf, usize), error::Error> {
     let mut download_path = download_path;
     // checks if the download path exists, and tries to create the folders if it doesn't
     if !download_path.exists() {
@@ -22,19 +30,49 @@ pub fn download(link: &str, download_path: PathBuf) -> Result<(PathBuf, usize),
         return Ok((download_path, 0));
     }
 
-    let client = reqwest::blocking::Client::new();
-    let mut resp = client.get(link).send().context(error::ReqwestError {
-        details: format!("Could not get {}", link),
+    // The deadline of the download step bounds the whole transfer.
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()
+        .context(error::ReqwestError {
+            details: "Could not build HTTP client",
+        })?;
+    let mut resp = client.get(link).send().map_err(|err| {
+        if err.is_timeout() {
+            timed_out(link, &err)
+        } else {
+            error::ReqwestError {
+                details: format!("Could not get {}", link),
+            }
+            .into_error(err)
+        }
     })?;
 
     if resp.status().is_success() {
+        let last_modified = resp
+            .headers()
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
         let chunk_size = 1024usize;
         let mut buffer: Vec<u8> = Vec::new();
 
         loop {
             let mut small_buffer = vec![0; chunk_size];
-            let small_buffer_read = resp.read(&mut small_buffer[..]).context(error::IOError {
-                details: "Could not read buffer",
+            let small_buffer_read = resp.read(&mut small_buffer[..]).map_err(|err| {
+                // A timeout while reading the body comes as a reqwest error in an IO error.
+                let timeout = err
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+                    .map_or(false, reqwest::Error::is_timeout);
+                if timeout {
+                    timed_out(link, &err)
+                } else {
+                    error::IOError {
+                        details: "Could not read buffer",
+                    }
+                    .into_error(err)
+                }
             })?;
             small_buffer.truncate(small_buffer_read);
 
@@ -52,6 +90,8 @@ pub fn download(link: &str, download_path: PathBuf) -> Result<(PathBuf, usize),
         let size_disk = disk_file.write(&buffer).context(error::IOError {
             details: format!("Could not write to {}", download_path.display()),
         })?;
+        drop(disk_file);
+        provenance::record_download(link, &download_path, last_modified)?;
 
         Ok((download_path, size_disk))
     } else {
@@ -61,6 +101,22 @@ pub fn download(link: &str, download_path: PathBuf) -> Result<(PathBuf, usize),
     }
 }
 
+// A download cut short by the timeout of the download step, which times out like the other steps.
+fn timed_out(link: &str, err: &dyn std::error::Error) -> error::Error {
+    error::Error::TimeoutError {
+        details: format!("Could not download {} in time: {}", link, err),
+    }
+}
+
+// Whether there is something to download at 'link'. Only a definite answer from the server (404
+// Not Found) says no: if it can't be reached, we don't know better.
+pub async fn exists(link: &str) -> bool {
+    match reqwest::Client::new().head(link).send().await {
+        Ok(resp) => resp.status() != reqwest::StatusCode::NOT_FOUND,
+        Err(_) => true,
+    }
+}
+
 pub fn get_filename_from_url(link: &str) -> Result<String, error::Error> {
     let url = Url::parse(link).context(error::URLError {
         details: format!("Could not parse URL {}", link),
@@ -80,3 +136,27 @@ pub fn get_filename_from_url(link: &str) -> Result<String, error::Error> {
         Ok(String::from(last))
     }
 }
+
+// Extract the archive in the directory 'dir', and remove the archive.
+pub fn unzip(archive: &Path, dir: &Path) -> Result<(), error::Error> {
+    let mut command = Command::new("unzip");
+    // We want to unzip in the directory 'dir'
+    command.arg("-d").arg(dir);
+    // We want to overwrite files without prompting
+    command.arg("-o");
+    command.arg(archive);
+    let output = command.output().context(error::IOError {
+        details: format!("Could not unzip {}", archive.display()),
+    })?;
+    // We don't need the zip file anymore, so remove it.
+    fs::remove_file(archive).context(error::IOError {
+        details: format!("Could not remove {}", archive.display()),
+    })?;
+    if !output.status.success() {
+        Err(error::Error::MiscError {
+            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
+        })
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use snafu::ResultExt;
+use std::collections::HashMap;
+use url::Url;
+
+use super::error;
+
+// The mimirsbrunn document type of each index type, as found in index and alias names.
+pub fn doc_type(index_type: &str) -> Option<&'static str> {
+    match index_type {
+        "admins" => Some("admin"),
+        "streets" => Some("street"),
+        "addresses" => Some("addr"),
+        "pois" => Some("poi"),
+        "stops" => Some("stop"),
+        _ => None,
+    }
+}
+
+// The alias mimirsbrunn maintains for an index type and a dataset, eg 'munin_addr_fr'.
+pub fn alias(index_type: &str, dataset: &str) -> Result<String, error::Error> {
+    let doc_type = doc_type(index_type).ok_or(error::Error::MiscError {
+        details: format!("No elasticsearch document type for {}", index_type),
+    })?;
+    Ok(format!("munin_{}_{}", doc_type, dataset))
+}
+
+// The elasticsearch index the alias currently points to. mimirsbrunn creates a new index
+// (eg 'munin_addr_fr_20200701_120000') on each run, and moves the alias once it is done.
+pub async fn resolve_alias(es: Url, alias: &str) -> Result<String, error::Error> {
+    let target = format!("{}_alias/{}", es.as_str(), alias);
+    let resp = reqwest::get(&target).await.context(error::ReqwestError {
+        details: format!("Could not get {}", target),
+    })?;
+    // When the alias is missing, elasticsearch answers 404 with an error, not with aliases.
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(error::Error::MiscError {
+            details: format!("No elasticsearch index behind alias {}", alias),
+        });
+    }
+    if !resp.status().is_success() {
+        return Err(error::Error::MiscError {
+            details: format!("Could not get {}: {}", target, resp.status()),
+        });
+    }
+    let body = resp.text().await.context(error::ReqwestError {
+        details: format!("Could not read aliases from {}", target),
+    })?;
+    // The answer maps each index to its aliases.
+    let indexes: HashMap<String, serde_json::Value> =
+        serde_json::from_str(&body).context(error::SerdeJSONError {
+            details: format!("Could not deserialize aliases from {}", target),
+        })?;
+    let mut indexes = indexes
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    // Should there be several indexes, the most recent is the last.
+    indexes.sort();
+    indexes.pop().ok_or(error::Error::MiscError {
+        details: format!("No elasticsearch index behind alias {}", alias),
+    })
+}
+
+// Delete an elasticsearch index. The aliases pointing to it go along.
+pub async fn delete_index(es: Url, index: &str) -> Result<(), error::Error> {
+    let target = format!("{}{}", es.as_str(), index);
+    let resp = reqwest::Client::new()
+        .delete(&target)
+        .send()
+        .await
+        .context(error::ReqwestError {
+            details: format!("Could not delete {}", target),
+        })?;
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        Err(error::Error::MiscError {
+            details: format!("Could not delete {}: {}", target, resp.status()),
+        })
+    }
+}
+
+// The mimirsbrunn dataset of an index: the region, made suitable for elasticsearch index names,
+// behind a configurable prefix.
+pub fn dataset_name(prefix: &str, region: &str) -> String {
+    format!("{}{}", prefix, region)
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_the_dataset_from_the_region() {
+        assert_eq!(dataset_name("", "ile-de-france"), "ile-de-france");
+        assert_eq!(dataset_name("fr_", "ile-de-france"), "fr_ile-de-france");
+        assert_eq!(dataset_name("", "2A"), "2a");
+    }
+
+    #[test]
+    fn makes_the_dataset_fit_for_index_names() {
+        assert_eq!(dataset_name("FR.", "Île de France"), "fr__le_de_france");
+        assert_eq!(dataset_name("", "fr/idf:1"), "fr_idf_1");
+    }
+
+    #[test]
+    fn names_the_alias_after_the_document_type() {
+        assert_eq!(alias("addresses", "fr").unwrap(), "munin_addr_fr");
+        assert_eq!(alias("admins", "fr").unwrap(), "munin_admin_fr");
+        assert!(alias("buildings", "fr").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::error;
+
+// The format of a dataset given by URL. The format tells us how to extract the download
+// and which XXX2mimir tool is going to index it.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    OsmPbf,
+    BanoCsv,
+    OpenaddressesCsv,
+    NtfsZip,
+    GtfsZip,
+    CosmogonyJson,
+}
+
+impl Format {
+    // The index types that can be produced from this format.
+    pub fn index_types(self) -> &'static [&'static str] {
+        match self {
+            Format::OsmPbf => &["admins", "streets", "pois"],
+            Format::BanoCsv => &["addresses"],
+            Format::OpenaddressesCsv => &["addresses"],
+            Format::NtfsZip => &["stops"],
+            Format::GtfsZip => &["stops"],
+            Format::CosmogonyJson => &["admins"],
+        }
+    }
+
+    // Archives are extracted in a directory after download.
+    pub fn is_archive(self) -> bool {
+        matches!(self, Format::NtfsZip | Format::GtfsZip)
+    }
+}
+
+impl FromStr for Format {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "osm-pbf" => Ok(Format::OsmPbf),
+            "bano-csv" => Ok(Format::BanoCsv),
+            "openaddresses-csv" => Ok(Format::OpenaddressesCsv),
+            "ntfs-zip" => Ok(Format::NtfsZip),
+            "gtfs-zip" => Ok(Format::GtfsZip),
+            "cosmogony-json" => Ok(Format::CosmogonyJson),
+            _ => Err(error::Error::MiscError {
+                details: format!(
+                    "Unknown format '{}', expected one of osm-pbf, bano-csv, openaddresses-csv, \
+                     ntfs-zip, gtfs-zip, cosmogony-json",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Format::OsmPbf => "osm-pbf",
+            Format::BanoCsv => "bano-csv",
+            Format::OpenaddressesCsv => "openaddresses-csv",
+            Format::NtfsZip => "ntfs-zip",
+            Format::GtfsZip => "gtfs-zip",
+            Format::CosmogonyJson => "cosmogony-json",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: &[Format] = &[
+        Format::OsmPbf,
+        Format::BanoCsv,
+        Format::OpenaddressesCsv,
+        Format::NtfsZip,
+        Format::GtfsZip,
+        Format::CosmogonyJson,
+    ];
+
+    #[test]
+    fn parses_what_it_displays() {
+        for format in FORMATS {
+            assert_eq!(format.to_string().parse::<Format>().unwrap(), *format);
+        }
+    }
+
+    #[test]
+    fn parses_the_names_used_in_json() {
+        for format in FORMATS {
+            let json = serde_json::to_string(format).unwrap();
+            assert_eq!(json.trim_matches('"').parse::<Format>().unwrap(), *format);
+        }
+    }
+
+    #[test]
+    fn refuses_unknown_formats() {
+        for s in &["", "pbf", "OSM-PBF", "osm_pbf", "csv"] {
+            assert!(s.parse::<Format>().is_err(), "'{}' was accepted", s);
+        }
+    }
+}
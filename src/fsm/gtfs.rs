@@ -0,0 +1,265 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+use super::download;
+use super::elasticsearch;
+use super::error;
+use super::logs::Log;
+use super::ntfs;
+use super::tools::{GTFS2NTFS, NTFS2MIMIR};
+use super::{DataSource, Job, Reporter};
+
+// The answer of elasticsearch to a _count request
+#[derive(Debug, Deserialize)]
+struct Count {
+    count: usize,
+}
+
+// Download the GTFS feed of a region, and extract it.
+// It will create a directory 'gtfs/<region>' inside the working directory (if not already
+// present), and the feed is extracted there.
+pub fn download_gtfs_region(
+    working_dir: PathBuf,
+    feed: &str,
+    region: &str,
+    timeout: Option<Duration>,
+) -> Result<PathBuf, error::Error> {
+    let mut filepath = working_dir;
+    filepath.push("gtfs");
+    filepath.push(region);
+    if !filepath.is_dir() {
+        std::fs::create_dir_all(filepath.as_path()).context(error::IOError {
+            details: format!(
+                "Expected to download GTFS file in {}, which is not a directory",
+                filepath.display()
+            ),
+        })?;
+    }
+    let res = download::download(feed, filepath.clone(), timeout)?;
+    download::unzip(res.0.as_path(), filepath.as_path())?;
+    Ok(filepath)
+}
+
+// Convert a GTFS directory into an NTFS directory, which ntfs2mimir can index.
+// The conversion is done by gtfs2ntfs (from transit_model), and the NTFS goes in
+// the directory 'gtfs2ntfs' inside the working directory.
+pub async fn convert_gtfs(
+    transit_model_dir: PathBuf,
+    working_dir: PathBuf,
+    inputpath: PathBuf,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
+) -> Result<PathBuf, error::Error> {
+    let dirname = inputpath
+        .file_name()
+        .ok_or(error::Error::MiscError {
+            details: format!("Could not get GTFS directory name {}", inputpath.display()),
+        })?
+        .to_owned();
+    let mut outputpath = working_dir;
+    outputpath.push("gtfs2ntfs");
+    outputpath.push(dirname);
+    if !outputpath.is_dir() {
+        std::fs::create_dir_all(outputpath.as_path()).context(error::IOError {
+            details: format!(
+                "Could not create output directory for NTFS {}",
+                outputpath.display()
+            ),
+        })?;
+    }
+    let mut execpath = transit_model_dir;
+    execpath.push(GTFS2NTFS);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
+    command
+        .arg("--input")
+        .arg(inputpath)
+        .arg("--output")
+        .arg(outputpath.clone());
+    log.run(&mut command, GTFS2NTFS, reporter).await?;
+    Ok(outputpath)
+}
+
+// Count the stop areas (location_type = 1) in the stops.txt of an NTFS directory.
+pub fn count_stop_areas(ntfs_dir: &Path) -> Result<usize, error::Error> {
+    let stops = ntfs_dir.join("stops.txt");
+    let mut reader = csv::Reader::from_path(&stops).context(error::CSVError {
+        details: format!("Could not open {}", stops.display()),
+    })?;
+    let column = reader
+        .headers()
+        .context(error::CSVError {
+            details: format!("Could not read headers of {}", stops.display()),
+        })?
+        .iter()
+        .position(|header| header == "location_type")
+        .ok_or(error::Error::MiscError {
+            details: format!("No location_type in {}", stops.display()),
+        })?;
+    let mut count = 0;
+    for record in reader.records() {
+        let record = record.context(error::CSVError {
+            details: format!("Could not read record of {}", stops.display()),
+        })?;
+        if record.get(column) == Some("1") {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// Make sure elasticsearch holds as many stops as there are stop areas in the dataset.
+// ntfs2mimir indexes the stop areas in the 'munin_stop_<dataset>' alias.
+pub async fn validate_stop_areas(
+    es: Url,
+    dataset: &str,
+    expected: usize,
+) -> Result<(), error::Error> {
+    let target = format!(
+        "{}{}/_count",
+        es.as_str(),
+        elasticsearch::alias("stops", dataset)?
+    );
+    let body = reqwest::get(&target)
+        .await
+        .context(error::ReqwestError {
+            details: format!("Could not get {}", target),
+        })?
+        .text()
+        .await
+        .context(error::ReqwestError {
+            details: format!("Could not read count from {}", target),
+        })?;
+    let count: Count = serde_json::from_str(&body).context(error::SerdeJSONError {
+        details: format!("Could not deserialize count from {}", target),
+    })?;
+    if count.count != expected {
+        Err(error::Error::MiscError {
+            details: format!(
+                "Expected {} stop areas in elasticsearch, found {}",
+                expected, count.count
+            ),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Convert a GTFS to NTFS, and count its stop areas, so that we can check later
+// that they all made it into elasticsearch.
+pub async fn process_gtfs(
+    job: &mut Job,
+    file_path: PathBuf,
+    reporter: &mut Reporter<'_>,
+) -> Result<PathBuf, error::Error> {
+    let path = convert_gtfs(
+        job.transit_model_dir.clone(),
+        job.working_dir.clone(),
+        file_path,
+        &job.log,
+        reporter,
+    )
+    .await?;
+    job.stop_areas = Some(count_stop_areas(&path)?);
+    Ok(path)
+}
+
+// Check the stop areas counted during the processing step, if any.
+pub async fn validate_gtfs(job: &Job) -> Result<(), error::Error> {
+    match job.stop_areas {
+        Some(expected) => validate_stop_areas(job.es.clone(), job.dataset(), expected).await,
+        None => Ok(()),
+    }
+}
+
+// GTFS feeds configured per region, converted to NTFS and indexed with ntfs2mimir
+pub struct Gtfs {
+    pub feeds: HashMap<String, String>, // The URL of the GTFS feed for each region
+}
+
+#[async_trait]
+impl DataSource for Gtfs {
+    fn name(&self) -> &'static str {
+        "gtfs"
+    }
+
+    fn index_types(&self) -> &'static [&'static str] {
+        &["stops"]
+    }
+
+    fn tools(&self) -> &'static [&'static str] {
+        &[GTFS2NTFS, NTFS2MIMIR]
+    }
+
+    async fn check_region(&self, region: &str) -> Result<(), error::Error> {
+        if self.feeds.contains_key(region) {
+            Ok(())
+        } else {
+            Err(error::Error::MiscError {
+                details: format!("No GTFS feed configured for region {}", region),
+            })
+        }
+    }
+
+    // The download blocks, so it runs on the blocking pool.
+    async fn download(
+        &self,
+        job: &mut Job,
+        _reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        let feed = self.feeds.get(&job.region).ok_or(error::Error::MiscError {
+            details: format!("No GTFS feed configured for region {}", &job.region),
+        })?;
+        let feed = feed.clone();
+        let working_dir = job.working_dir.clone();
+        let region = job.region.clone();
+        let timeout = job.log.remaining();
+        tokio::task::spawn_blocking(move || {
+            download_gtfs_region(working_dir, &feed, &region, timeout)
+        })
+        .await
+        .context(error::TokioJoinError {
+            details: format!("Could not download GTFS for region {}", job.region),
+        })?
+    }
+
+    fn needs_processing(&self, _job: &Job) -> bool {
+        true
+    }
+
+    async fn process(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        process_gtfs(job, file_path, reporter).await
+    }
+
+    // GTFS has been converted to NTFS during the processing step.
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        ntfs::index_ntfs_region(
+            job.mimirs_dir.clone(),
+            job.es.clone(),
+            file_path,
+            &job.options,
+            &job.log,
+            reporter,
+        )
+        .await
+    }
+
+    async fn validate(&self, job: &mut Job) -> Result<(), error::Error> {
+        validate_gtfs(job).await
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+use super::artifacts::Lease;
+use super::format::Format;
+use super::logs::Log;
+use super::options::Options;
+use super::provenance::{self, Provenance, StepRun};
+use super::{Record, DEFAULT_DATASET};
+
+// Everything a data source needs to know about the index it is working on.
+pub struct Job {
+    pub index_type: String,         // eg admins, streets, addresses, ...
+    pub region: String,             // The region we need to index
+    pub working_dir: PathBuf,       // Where all the files will go (download, processed, ...)
+    pub mimirs_dir: PathBuf,        // Where we can find executables XXX2mimir
+    pub cosmogony_dir: PathBuf,     // Where we can find cosmogony
+    pub transit_model_dir: PathBuf, // Where we can find gtfs2ntfs
+    pub es: Url,                    // How we connect to elasticsearch
+    pub url: Option<Url>,           // Where to download the dataset from, for the 'url' data source
+    pub options: Options,           // How to tune the XXX2mimir tools
+    pub format: Option<Format>,     // The declared format of the dataset, for the 'url' data source
+    pub stop_areas: Option<usize>,  // The number of stop areas we expect to find once indexed
+    pub record: Option<Record>,     // What we have not yet published about the index
+    pub leases: Vec<Lease>,         // The shared artifacts we use, released when we're done
+    pub log: Log,                   // Where the output of the tools goes
+    pub provenance: Provenance,     // How the index is produced, so far
+}
+
+impl Job {
+    // The mimirsbrunn dataset given to the tools.
+    pub fn dataset(&self) -> &str {
+        self.options.dataset.as_deref().unwrap_or(DEFAULT_DATASET)
+    }
+
+    // The record to be published with the next state.
+    pub fn record(&mut self) -> &mut Record {
+        self.record.get_or_insert_with(Record::default)
+    }
+
+    // The dataset at 'path' is what we index.
+    pub fn track_inputs(&mut self, path: &Path) {
+        self.provenance.inputs = provenance::inputs(path);
+        self.record().provenance = Some(self.provenance.clone());
+    }
+
+    // Keep track of the tools run during a step, and of its duration if it completed.
+    pub fn track_step(&mut self, step: &str, duration: Option<Duration>) {
+        self.provenance.commands.extend(self.log.take_runs());
+        if let Some(duration) = duration {
+            self.provenance.steps.push(StepRun {
+                step: String::from(step),
+                duration,
+            });
+        }
+        self.record().provenance = Some(self.provenance.clone());
+    }
+}
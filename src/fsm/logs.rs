@@ -0,0 +1,482 @@
+use serde::Serialize;
+use slog::{info, o, warn, Logger};
+use snafu::ResultExt;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::time::{delay_for, timeout};
+
+use super::error;
+use super::parsers::{self, Parser};
+use super::provenance::CommandRun;
+use super::{Progress, Reporter};
+use crate::settings::Tools;
+
+// The steps of the FSM which may run external tools, in the order they are run.
+pub const DOWNLOAD: &str = "download";
+pub const PROCESSING: &str = "processing";
+pub const INDEXING: &str = "indexing";
+pub const VALIDATION: &str = "validation";
+pub const STEPS: &[&str] = &[DOWNLOAD, PROCESSING, INDEXING, VALIDATION];
+
+// How many lines a slow follower can miss before being skipped ahead.
+const FOLLOWERS_CAPACITY: usize = 1024;
+
+// How often we check on a tool which closed its output, when it has a deadline.
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+// How many lines of stderr we keep to report a failure.
+const ERROR_LINES: usize = 20;
+
+// A line written by a tool.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogLine {
+    pub step: String,
+    pub stream: String, // stdout or stderr
+    pub line: String,
+}
+
+// The output of the tools run for each index. It goes to a log file per index and per step
+// (<working_dir>/logs/<index_id>/<step>.log), and to whoever follows the index while it runs.
+// The tools are run within the timeouts and limits of the settings.
+#[derive(Clone)]
+pub struct Logs {
+    dir: PathBuf,
+    followers: Arc<Mutex<HashMap<i32, broadcast::Sender<LogLine>>>>,
+    tools: Tools,
+    versions: Arc<HashMap<String, String>>, // The versions of the tools, for the provenance
+}
+
+impl Logs {
+    pub fn new<P: AsRef<Path>>(working_dir: P, tools: &Tools) -> Self {
+        Logs {
+            dir: working_dir.as_ref().join("logs"),
+            followers: Arc::new(Mutex::new(HashMap::new())),
+            tools: tools.clone(),
+            versions: Arc::new(HashMap::new()),
+        }
+    }
+
+    // The versions of the tools, as found when checking them.
+    pub fn with_versions(self, versions: HashMap<String, String>) -> Self {
+        Logs {
+            versions: Arc::new(versions),
+            ..self
+        }
+    }
+
+    // The log file of a step, or the directory of all the log files when there is no step.
+    pub fn path(&self, index_id: i32, step: Option<&str>) -> PathBuf {
+        let dir = self.dir.join(index_id.to_string());
+        match step {
+            Some(step) => dir.join(format!("{}.log", step)),
+            None => dir,
+        }
+    }
+
+    // The last 'tail' lines logged for an index, for one step or for all of them.
+    pub fn read(
+        &self,
+        index_id: i32,
+        step: Option<&str>,
+        tail: Option<usize>,
+    ) -> Result<Vec<LogLine>, error::Error> {
+        let steps = match step {
+            Some(step) if STEPS.contains(&step) => vec![step],
+            Some(step) => {
+                return Err(error::Error::MiscError {
+                    details: format!("Unknown step '{}', expected one of {:?}", step, STEPS),
+                })
+            }
+            None => STEPS.to_vec(),
+        };
+        let mut lines = Vec::new();
+        for step in steps {
+            let path = self.path(index_id, Some(step));
+            if !path.is_file() {
+                continue;
+            }
+            let mut content = String::new();
+            File::open(&path)
+                .and_then(|mut file| file.read_to_string(&mut content))
+                .context(error::IOError {
+                    details: format!("Could not read log file {}", path.display()),
+                })?;
+            lines.extend(content.lines().map(|line| parse_line(step, line)));
+        }
+        if let Some(tail) = tail {
+            let skip = lines.len().saturating_sub(tail);
+            lines.drain(..skip);
+        }
+        Ok(lines)
+    }
+
+    // The lines logged from now on for an index. None if nothing runs for the index, otherwise
+    // the receiver is closed once its job is done.
+    pub fn follow(&self, index_id: i32) -> Option<broadcast::Receiver<LogLine>> {
+        self.followers
+            .lock()
+            .unwrap()
+            .get(&index_id)
+            .map(broadcast::Sender::subscribe)
+    }
+
+    // Whether a job is running for the index.
+    pub fn is_running(&self, index_id: i32) -> bool {
+        self.followers.lock().unwrap().contains_key(&index_id)
+    }
+
+    // Remove the log files of an index. Returns the directory removed, if there was one.
+    pub fn remove(&self, index_id: i32) -> Result<Option<PathBuf>, error::Error> {
+        let dir = self.path(index_id, None);
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        fs::remove_dir_all(&dir).context(error::IOError {
+            details: format!("Could not remove log directory {}", dir.display()),
+        })?;
+        Ok(Some(dir))
+    }
+
+    // Where the job of an index logs the output of its tools.
+    pub fn log(&self, index_id: i32, data_source: &str, logger: &Logger) -> Log {
+        self.attach(
+            index_id,
+            data_source,
+            logger.new(o!("index_id" => index_id)),
+        )
+    }
+
+    fn attach(&self, index_id: i32, data_source: &str, logger: Logger) -> Log {
+        let (sender, _) = broadcast::channel(FOLLOWERS_CAPACITY);
+        self.followers
+            .lock()
+            .unwrap()
+            .insert(index_id, sender.clone());
+        Log {
+            logs: self.clone(),
+            index_id,
+            data_source: String::from(data_source),
+            step: String::from(DOWNLOAD),
+            deadline: None,
+            runs: Mutex::new(Vec::new()),
+            sender,
+            logger,
+        }
+    }
+}
+
+impl std::fmt::Debug for Logs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Logs").field("dir", &self.dir).finish()
+    }
+}
+
+// The log file holds lines such as '[stderr] Could not connect'.
+fn parse_line(step: &str, line: &str) -> LogLine {
+    let (stream, line) = if let Some(line) = line.strip_prefix("[stdout] ") {
+        ("stdout", line)
+    } else if let Some(line) = line.strip_prefix("[stderr] ") {
+        ("stderr", line)
+    } else {
+        ("", line)
+    };
+    LogLine {
+        step: String::from(step),
+        stream: String::from(stream),
+        line: String::from(line),
+    }
+}
+
+// The logs of a single job.
+pub struct Log {
+    logs: Logs,
+    index_id: i32,
+    data_source: String,
+    step: String, // The step of the FSM we're in, which tells the log file
+    deadline: Option<(Instant, Duration)>, // When the tools of the step must be done, if ever
+    runs: Mutex<Vec<CommandRun>>, // The tools run since they were last taken
+    sender: broadcast::Sender<LogLine>,
+    logger: Logger,
+}
+
+impl Log {
+    // The same log, followed through other logs (eg those shared by the server).
+    pub fn reattach(&self, logs: &Logs) -> Log {
+        logs.attach(self.index_id, &self.data_source, self.logger.clone())
+    }
+
+    // The tools run so far, for the provenance of the index.
+    pub fn take_runs(&self) -> Vec<CommandRun> {
+        std::mem::take(&mut *self.runs.lock().unwrap())
+    }
+
+    // The time left to the step, if it has a timeout, eg to bound a download.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|(deadline, _)| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    // The timeout of the step starts now.
+    pub fn set_step(&mut self, step: &str) {
+        self.step = String::from(step);
+        self.deadline = self
+            .logs
+            .tools
+            .timeout(&self.data_source, step)
+            .map(|timeout| (Instant::now() + timeout, timeout));
+    }
+
+    // The command to run a tool within the limits of the settings. On Linux, the tool gets its
+    // own process group (so that it can be killed along with its children), and the limits are
+    // applied with nice, prlimit and taskset.
+    #[cfg(target_os = "linux")]
+    pub fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        let tools = &self.logs.tools;
+        let mut command = Command::new("setsid");
+        if let Some(nice) = tools.nice {
+            command.arg("nice").arg("-n").arg(nice.to_string());
+        }
+        if let Some(memory_limit) = tools.memory_limit {
+            command
+                .arg("prlimit")
+                .arg(format!("--as={}", memory_limit * 1024 * 1024));
+        }
+        if let Some(cpus) = &tools.cpus {
+            let cpus = cpus.iter().map(usize::to_string).collect::<Vec<_>>();
+            command.arg("taskset").arg("-c").arg(cpus.join(","));
+        }
+        command.arg(program);
+        command
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        Command::new(program)
+    }
+
+    // Run the command to completion, logging its output as it comes, and reporting the progress
+    // found in it. On failure, the end of stderr is reported in the error.
+    pub async fn run(
+        &self,
+        command: &mut Command,
+        tool: &str,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        let path = self.logs.path(self.index_id, Some(&self.step));
+        let file = open_log_file(&path)?;
+        let file = Arc::new(Mutex::new(file));
+        info!(self.logger, "Running {}", tool; "step" => &self.step, "log" => path.display().to_string());
+
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(error::IOError {
+                details: format!("Could not run {} command", tool),
+            })?;
+        let started = Instant::now();
+
+        // Both streams go through the same parser, since tools don't agree on where to log.
+        let parser = Arc::new(Mutex::new(parsers::parser(tool)));
+        let (progress, mut receiver) = unbounded_channel();
+        let stdout = child.stdout.take().map(|out| {
+            let progress = progress.clone();
+            self.forward(out, "stdout", tool, file.clone(), parser.clone(), progress)
+        });
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| self.forward(err, "stderr", tool, file, parser, progress));
+
+        let deadline = self.deadline.map(|(deadline, _)| deadline);
+        loop {
+            // The channel is closed once both streams are closed.
+            let next = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => timeout(remaining, receiver.recv()).await.unwrap_or(None),
+                    None => None,
+                },
+                None => receiver.recv().await,
+            };
+            match next {
+                Some(progress) => reporter.progress(progress).await,
+                None => break,
+            }
+        }
+
+        // The streams may be closed before the tool is done.
+        let status = wait(&mut child, deadline).await.context(error::IOError {
+            details: format!("Could not wait for {}", tool),
+        })?;
+        let status = match status {
+            Some(status) => status,
+            None => {
+                self.kill(&mut child, tool);
+                self.record(command, tool, started, false);
+                let timeout = self
+                    .deadline
+                    .map(|(_, timeout)| timeout)
+                    .unwrap_or_default();
+                return Err(error::Error::TimeoutError {
+                    details: format!(
+                        "{} did not complete the {} step within {}s",
+                        tool,
+                        self.step,
+                        timeout.as_secs()
+                    ),
+                });
+            }
+        };
+
+        if let Some(handle) = stdout {
+            let _ = handle.join();
+        }
+        let errors = stderr
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+
+        self.record(command, tool, started, status.success());
+        if status.success() {
+            Ok(())
+        } else {
+            Err(error::Error::MiscError {
+                details: format!(
+                    "{} failed ({}) => {}",
+                    tool,
+                    status,
+                    Vec::from(errors).join("\n")
+                ),
+            })
+        }
+    }
+
+    // Remember how the tool was run.
+    fn record(&self, command: &Command, tool: &str, started: Instant, success: bool) {
+        self.runs.lock().unwrap().push(CommandRun {
+            step: self.step.clone(),
+            tool: String::from(tool),
+            version: self.logs.versions.get(tool).cloned(),
+            // Command's Debug gives the program and its arguments, quoted.
+            command_line: format!("{:?}", command),
+            duration: started.elapsed(),
+            success,
+        });
+    }
+
+    // Kill the tool, and whatever it started.
+    fn kill(&self, child: &mut Child, tool: &str) {
+        warn!(self.logger, "Killing {} after timeout", tool; "step" => &self.step);
+        // The tool leads its own process group.
+        #[cfg(target_os = "linux")]
+        let killed = Command::new("kill")
+            .arg("-KILL")
+            .arg("--")
+            .arg(format!("-{}", child.id()))
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        #[cfg(not(target_os = "linux"))]
+        let killed = false;
+        if !killed {
+            if let Err(err) = child.kill() {
+                warn!(self.logger, "Could not kill {}: {}", tool, err);
+            }
+        }
+        // Reap the process, and let go of the streams.
+        let _ = child.wait();
+    }
+
+    // Copy each line of a stream of the tool to the log file, the followers and the logger,
+    // and send the progress found in it. Returns the last lines, to report errors.
+    fn forward<R: Read + Send + 'static>(
+        &self,
+        stream: R,
+        name: &'static str,
+        tool: &str,
+        file: Arc<Mutex<File>>,
+        parser: Arc<Mutex<Box<dyn Parser>>>,
+        progress: UnboundedSender<Progress>,
+    ) -> thread::JoinHandle<VecDeque<String>> {
+        let step = self.step.clone();
+        let sender = self.sender.clone();
+        let logger = self
+            .logger
+            .new(o!("tool" => String::from(tool), "stream" => name));
+        thread::spawn(move || {
+            let mut last = VecDeque::with_capacity(ERROR_LINES);
+            for line in BufReader::new(stream).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        warn!(logger, "Could not read output: {}", err);
+                        break;
+                    }
+                };
+                if let Err(err) = writeln!(file.lock().unwrap(), "[{}] {}", name, line) {
+                    warn!(logger, "Could not write log file: {}", err);
+                }
+                info!(logger, "{}", line);
+                if let Some(p) = parser.lock().unwrap().parse(&line) {
+                    // The job may not listen anymore, if it failed.
+                    let _ = progress.send(p);
+                }
+                // Nobody following is fine.
+                let _ = sender.send(LogLine {
+                    step: step.clone(),
+                    stream: String::from(name),
+                    line: line.clone(),
+                });
+                if last.len() == ERROR_LINES {
+                    last.pop_front();
+                }
+                last.push_back(line);
+            }
+            last
+        })
+    }
+}
+
+impl Drop for Log {
+    // Once the job is done, its followers are closed.
+    fn drop(&mut self) {
+        self.logs.followers.lock().unwrap().remove(&self.index_id);
+    }
+}
+
+// Wait for the child to exit, or for the deadline. None if the deadline passed first.
+// We poll rather than block on the child, so as not to hold up the runtime.
+async fn wait(child: &mut Child, deadline: Option<Instant>) -> std::io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return Ok(None);
+        }
+        delay_for(WAIT_INTERVAL).await;
+    }
+}
+
+fn open_log_file(path: &Path) -> Result<File, error::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(error::IOError {
+            details: format!("Could not create log directory {}", dir.display()),
+        })?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(error::IOError {
+            details: format!("Could not open log file {}", path.display()),
+        })
+}
@@ -1,21 +1,56 @@
-use async_zmq::{Message, MultipartIter, SinkExt};
 use serde::{Deserialize, Serialize};
-use slog::{info, o, Logger};
+use slog::{error, o, Logger};
 use snafu::ResultExt;
 use std::collections::VecDeque;
+use std::future::Future;
 use std::path::PathBuf;
-use std::time::{Duration, SystemTime};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use url::Url;
 
+mod artifacts;
 mod bano;
 mod cosmogony;
 mod download;
+mod elasticsearch;
+mod format;
+mod gtfs;
+mod job;
+mod logs;
+mod notify;
 mod ntfs;
+mod openaddresses;
+mod options;
 mod osm;
+mod parsers;
+mod provenance;
+mod remote;
+mod source;
+mod staged;
+mod tools;
+
+pub use artifacts::{Artifacts, Lease};
+pub use elasticsearch::{dataset_name, delete_index as delete_es_index, resolve_alias};
+pub use format::Format;
+pub use job::Job;
+pub use logs::{LogLine, Logs, STEPS};
+pub use notify::{Publisher, Reporter};
+pub use options::Options;
+pub use osm::fetch_osm_region;
+pub use provenance::{remove_download, Provenance};
+pub use source::{DataSource, Registry};
+pub use staged::resolve_staged_path;
+pub use tools::ToolCheck;
+
+use notify::Notifier;
 
 use crate::error;
 use crate::settings::Settings;
 
+// The dataset mimirsbrunn uses when none is given on the command line.
+const DEFAULT_DATASET: &str = "fr";
+
 // From https://gist.github.com/anonymous/ee3e4df093c136ced7b394dc7ffb78e1
 
 // Using internally tagged so it's more even across types.
@@ -27,8 +62,17 @@ use crate::settings::Settings;
 #[serde(tag = "type")]
 pub enum State {
     NotAvailable,
+    // The indexes which must be available before we can start (eg admins for streets).
+    WaitingForDependencies {
+        dependencies: Vec<i32>,
+    },
+    DependencyError {
+        details: String,
+    },
     DownloadingInProgress {
         started_at: SystemTime,
+        #[serde(default)]
+        progress: Option<Progress>,
     },
     DownloadingError {
         details: String,
@@ -40,6 +84,8 @@ pub enum State {
     ProcessingInProgress {
         file_path: PathBuf,
         started_at: SystemTime,
+        #[serde(default)]
+        progress: Option<Progress>,
     },
     ProcessingError {
         details: String,
@@ -51,6 +97,8 @@ pub enum State {
     IndexingInProgress {
         file_path: PathBuf,
         started_at: SystemTime,
+        #[serde(default)]
+        progress: Option<Progress>,
     },
     IndexingError {
         details: String,
@@ -63,12 +111,105 @@ pub enum State {
         details: String,
     },
     Available,
+    // A tool did not complete its step in time, and was killed.
+    TimedOut {
+        step: String,
+        details: String,
+    },
     Failure(String),
 }
 
+impl State {
+    // Whether the FSM has stopped on an error.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            State::DependencyError { .. }
+                | State::DownloadingError { .. }
+                | State::ProcessingError { .. }
+                | State::IndexingError { .. }
+                | State::ValidationError { .. }
+                | State::TimedOut { .. }
+                | State::Failure(_)
+        )
+    }
+
+    // The step of the pipeline we're in, for the states in progress.
+    pub fn step(&self) -> Option<&'static str> {
+        match self {
+            State::DownloadingInProgress { .. } => Some(logs::DOWNLOAD),
+            State::ProcessingInProgress { .. } => Some(logs::PROCESSING),
+            State::IndexingInProgress { .. } => Some(logs::INDEXING),
+            State::ValidationInProgress => Some(logs::VALIDATION),
+            _ => None,
+        }
+    }
+
+    // Record how far we are in the current step, for the states which can tell.
+    fn set_progress(&mut self, p: Progress) {
+        match self {
+            State::DownloadingInProgress { progress, .. }
+            | State::ProcessingInProgress { progress, .. }
+            | State::IndexingInProgress { progress, .. } => {
+                *progress = Some(p);
+            }
+            _ => {}
+        }
+    }
+
+    // How far we got in the current step, if we know.
+    pub fn progress(&self) -> Option<&Progress> {
+        match self {
+            State::DownloadingInProgress { progress, .. }
+            | State::ProcessingInProgress { progress, .. }
+            | State::IndexingInProgress { progress, .. } => progress.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+// How far we are in the current step, for steps made of several items (eg the departments
+// of a nationwide BANO download), or as told by the output of the tools (eg the number of
+// streets indexed by osm2mimir).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Progress {
+    // What we have just done, eg 'Downloaded BANO for department 75'
+    pub phase: String,
+    // How many items have been processed so far
+    pub count: u64,
+    // How many items there are to process, when known
+    pub total: Option<u64>,
+    // How far we are in the step, when the tool tells
+    #[serde(default)]
+    pub percentage: Option<f64>,
+}
+
+// Information about the index gathered while running the FSM (eg the metadata of the
+// dataset we downloaded). When there is something new, it is published along with the state,
+// so that it can be stored with the index.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Record {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    // The elasticsearch index created by the tools, and the alias pointing to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub es_index: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub es_alias: Option<String>,
+    // The last progress reported while indexing, eg how many objects were indexed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    // Where the data comes from, and how it was indexed, so far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 enum Event {
+    Wait,
+    DependencyError(String),
     Download,
+    Stage(PathBuf),
     DownloadingError(String),
     DownloadingComplete(PathBuf, Duration),
     Process(PathBuf),
@@ -80,47 +221,36 @@ enum Event {
     Validate,
     ValidationError(String),
     ValidationComplete,
+    Timeout(String, String),
     Reset,
 }
 
 pub struct FSM {
-    id: i32,                 // Id of the index, used to identify the published notifications.
-    state: State,            // Current state of the FSM
-    working_dir: PathBuf,    // Where all the files will go (download, processed, ...)
-    mimirs_dir: PathBuf,     // Where we can find executables XXX2mimir
-    cosmogony_dir: PathBuf,  // Where we can find cosmogony
-    events: VecDeque<Event>, // A queue of events
-    es: Url,                 // How we connect to elasticsearch
-    index_type: String,      // eg admin, streets, addresses, ...
-    data_source: String,     // eg OSM, BANO, ...
-    region: String,          // The region we need to index
-    topic: String,           // The topic we need to broadcast.
-    publish: async_zmq::publish::Publish<std::vec::IntoIter<Message>, Message>,
-    logger: Logger,
+    state: State,                 // Current state of the FSM
+    events: VecDeque<Event>,      // A queue of events
+    source: Arc<dyn DataSource>,  // Where the data comes from, and how to index it
+    job: Job,                     // What the data source needs to know about the index
+    staged_path: Option<PathBuf>, // A dataset already on disk, in which case we skip the download
+    dependencies: Vec<i32>,       // The indexes which must be available before we start
+    wait: Option<Dependencies>,   // Resolves once the dependencies are available
+    notifier: Notifier,           // How we publish our state
+    logger: Logger,               // Where we report what went wrong
 }
 
+// A future which resolves once all the dependencies of an index are available, or as soon as
+// one of them fails.
+pub type Dependencies = Pin<Box<dyn Future<Output = Result<(), error::Error>> + Send>>;
+
 impl FSM {
     pub fn new<S: Into<String>>(
         index_id: i32,
         index_type: S,
-        data_source: S,
+        source: Arc<dyn DataSource>,
         region: S,
         settings: &Settings,
-        topic: S,
+        publisher: Publisher,
         logger: Logger,
     ) -> Result<Self, error::Error> {
-        let zmq_endpoint = format!("tcp://{}:{}", settings.zmq.host, settings.zmq.port);
-        let zmq = async_zmq::publish(&zmq_endpoint)
-            .context(error::ZMQSocketError {
-                details: format!("Could not publish on endpoint '{}'", &zmq_endpoint),
-            })?
-            .bind()
-            .context(error::ZMQError {
-                details: format!(
-                    "Could not bind socket for publication on endpoint '{}'",
-                    &zmq_endpoint
-                ),
-            })?;
         let elasticsearch_endpoint = format!(
             "http://{}:{}",
             settings.elasticsearch.host, settings.elasticsearch.port
@@ -131,28 +261,131 @@ impl FSM {
                 &elasticsearch_endpoint
             ),
         })?;
-        let fsm_logger = logger.new(o!("zmq" => zmq_endpoint));
+        let fsm_logger = logger.new(o!("zmq" => String::from(publisher.endpoint())));
+        let log = Logs::new(&settings.work.working_dir, &settings.tools).log(
+            index_id,
+            source.name(),
+            &logger,
+        );
         Ok(FSM {
-            id: index_id,
             state: State::NotAvailable,
-            working_dir: PathBuf::from(&settings.work.working_dir),
-            mimirs_dir: PathBuf::from(&settings.work.mimirsbrunn_dir),
-            cosmogony_dir: PathBuf::from(&settings.work.cosmogony_dir),
             events: VecDeque::new(),
-            es: elasticsearch_url,
-            index_type: index_type.into(),
-            data_source: data_source.into(),
-            region: region.into(),
-            topic: topic.into(),
-            publish: zmq,
-            logger: fsm_logger,
+            source,
+            job: Job {
+                index_type: index_type.into(),
+                region: region.into(),
+                working_dir: PathBuf::from(&settings.work.working_dir),
+                mimirs_dir: PathBuf::from(&settings.work.mimirsbrunn_dir),
+                cosmogony_dir: PathBuf::from(&settings.work.cosmogony_dir),
+                transit_model_dir: PathBuf::from(&settings.work.transit_model_dir),
+                es: elasticsearch_url,
+                url: None,
+                options: Options::default(),
+                format: None,
+                stop_areas: None,
+                record: None,
+                leases: Vec::new(),
+                log,
+                provenance: Provenance::default(),
+            },
+            staged_path: None,
+            dependencies: Vec::new(),
+            wait: None,
+            notifier: Notifier::new(index_id, publisher, fsm_logger),
+            logger,
         })
     }
+
+    // Use a dataset which is already present on disk, rather than downloading it.
+    // The path is trusted, so it is expected to have been checked with resolve_staged_path, or
+    // to have been downloaded by us (eg the PBF shared by the indexes of a region bundle).
+    pub fn with_staged_path(mut self, file_path: PathBuf) -> Self {
+        self.staged_path = Some(file_path);
+        self
+    }
+
+    // Tune the XXX2mimir tools. The options are expected to have been checked with
+    // Options::parse.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.job.options = options;
+        self
+    }
+
+    // Log the output of the tools so that it can be followed while they run.
+    pub fn with_logs(mut self, logs: &Logs) -> Self {
+        self.job.log = self.job.log.reattach(logs);
+        self
+    }
+
+    // Use a dataset shared with other jobs, which is kept until we're done with it.
+    pub fn with_lease(mut self, lease: Lease) -> Self {
+        self.staged_path = Some(lease.path().to_path_buf());
+        self.job.leases.push(lease);
+        self
+    }
+
+    // Wait for the given indexes to be available before starting.
+    pub fn with_dependencies(mut self, dependencies: Vec<i32>, wait: Dependencies) -> Self {
+        self.dependencies = dependencies;
+        self.wait = Some(wait);
+        self
+    }
+
+    // Wait for the job of 'index_id', which has the same index type, data source and region,
+    // to be done before starting. 'turn' resolves once it is, and is awaited after the
+    // dependencies.
+    pub fn with_queue(mut self, index_id: i32, turn: Dependencies) -> Self {
+        self.dependencies.insert(0, index_id);
+        self.wait = Some(match self.wait.take() {
+            Some(wait) => Box::pin(async move {
+                wait.await?;
+                turn.await
+            }),
+            None => turn,
+        });
+        self
+    }
+
+    // The event which gets us going, once we don't have to wait anymore.
+    fn start(&self) -> Event {
+        match self.staged_path.clone() {
+            Some(file_path) => Event::Stage(file_path),
+            None => Event::Download,
+        }
+    }
+
+    // Download the dataset from an arbitrary URL, and route it to the tools matching its format.
+    pub fn with_remote(mut self, url: Url, format: Format) -> Self {
+        self.job.url = Some(url);
+        self.job.format = Some(format);
+        self
+    }
+
     async fn next(&mut self, event: Event) {
         match (&self.state, event) {
-            (State::NotAvailable, Event::Download) => {
+            (State::NotAvailable, Event::Wait) => {
+                self.state = State::WaitingForDependencies {
+                    dependencies: self.dependencies.clone(),
+                };
+            }
+            (State::WaitingForDependencies { .. }, Event::DependencyError(d)) => {
+                self.state = State::DependencyError { details: d };
+            }
+            (State::DependencyError { .. }, Event::Reset) => {
+                self.state = State::NotAvailable;
+            }
+            (State::NotAvailable, Event::Download)
+            | (State::WaitingForDependencies { .. }, Event::Download) => {
                 self.state = State::DownloadingInProgress {
                     started_at: SystemTime::now(),
+                    progress: None,
+                };
+            }
+            (State::NotAvailable, Event::Stage(ref p))
+            | (State::WaitingForDependencies { .. }, Event::Stage(ref p)) => {
+                self.state = State::Downloaded {
+                    file_path: p.clone(),
+                    duration: Duration::from_secs(0),
                 };
             }
             (State::DownloadingInProgress { .. }, Event::DownloadingError(ref d)) => {
@@ -173,6 +406,7 @@ impl FSM {
                 self.state = State::ProcessingInProgress {
                     file_path: p.clone(),
                     started_at: SystemTime::now(),
+                    progress: None,
                 };
             }
             (State::ProcessingInProgress { .. }, Event::ProcessingError(d)) => {
@@ -191,12 +425,14 @@ impl FSM {
                 self.state = State::IndexingInProgress {
                     file_path: p.clone(),
                     started_at: SystemTime::now(),
+                    progress: None,
                 };
             }
             (State::Downloaded { .. }, Event::Index(ref p)) => {
                 self.state = State::IndexingInProgress {
                     file_path: p.clone(),
                     started_at: SystemTime::now(),
+                    progress: None,
                 };
             }
             (State::IndexingInProgress { .. }, Event::IndexingError(d)) => {
@@ -211,6 +447,13 @@ impl FSM {
             (State::Indexed { .. }, Event::Validate) => {
                 self.state = State::ValidationInProgress;
             }
+            // Only the steps in progress run tools which can time out.
+            (_, Event::Timeout(step, details)) => {
+                self.state = State::TimedOut { step, details };
+            }
+            (State::TimedOut { .. }, Event::Reset) => {
+                self.state = State::NotAvailable;
+            }
             (State::ValidationInProgress, Event::ValidationError(d)) => {
                 self.state = State::ValidationError { details: d }
             }
@@ -228,252 +471,199 @@ impl FSM {
     }
 
     pub async fn run(&mut self) {
-        match &self.state {
+        // We borrow each field separately, so that the data source can publish its progress
+        // while it works on the job.
+        let start = self.start();
+        let FSM {
+            state,
+            events,
+            source,
+            job,
+            wait,
+            notifier,
+            ..
+        } = self;
+        match state.clone() {
             State::NotAvailable => {}
-            State::DownloadingInProgress { started_at } => match self.data_source.as_ref() {
-                "cosmogony" => {
-                    match osm::download_osm_region(self.working_dir.clone(), &self.region) {
-                        Ok(file_path) => {
-                            let duration = started_at.elapsed().unwrap();
-                            self.events
-                                .push_back(Event::DownloadingComplete(file_path, duration));
-                        }
-                        Err(err) => {
-                            self.events.push_back(Event::DownloadingError(format!(
-                                "Could not download: {}",
-                                err
-                            )));
-                        }
+            State::WaitingForDependencies { .. } => {
+                let res = match wait.take() {
+                    Some(wait) => wait.await,
+                    None => Ok(()),
+                };
+                match res {
+                    Ok(()) => {
+                        events.push_back(start);
                     }
-                }
-                "bano" => {
-                    match bano::download_bano_region(self.working_dir.clone(), &self.region) {
-                        Ok(file_path) => {
-                            let duration = started_at.elapsed().unwrap();
-                            self.events
-                                .push_back(Event::DownloadingComplete(file_path, duration));
-                        }
-                        Err(err) => {
-                            self.events.push_back(Event::DownloadingError(format!(
-                                "Could not download: {}",
-                                err
-                            )));
-                        }
+                    Err(err) => {
+                        events.push_back(Event::DependencyError(format!(
+                            "Could not wait for dependencies: {}",
+                            err
+                        )));
                     }
                 }
-                "osm" => match osm::download_osm_region(self.working_dir.clone(), &self.region) {
+            }
+            State::DependencyError { details: _ } => {
+                events.push_back(Event::Reset);
+            }
+            State::DownloadingInProgress { started_at, .. } => {
+                job.log.set_step(logs::DOWNLOAD);
+                let mut reporter = Reporter::new(state, notifier);
+                let res = source.download(job, &mut reporter).await;
+                let duration = started_at.elapsed().unwrap();
+                job.track_step(logs::DOWNLOAD, res.as_ref().ok().map(|_| duration));
+                match res {
                     Ok(file_path) => {
-                        let duration = started_at.elapsed().unwrap();
-                        self.events
-                            .push_back(Event::DownloadingComplete(file_path, duration));
+                        events.push_back(Event::DownloadingComplete(file_path, duration));
+                    }
+                    Err(err @ error::Error::TimeoutError { .. }) => {
+                        events.push_back(Event::Timeout(
+                            String::from(logs::DOWNLOAD),
+                            err.to_string(),
+                        ));
                     }
                     Err(err) => {
-                        self.events.push_back(Event::DownloadingError(format!(
-                            "Could not download: {}",
+                        events.push_back(Event::DownloadingError(format!(
+                            "Could not download {}: {}",
+                            source.name(),
                             err
                         )));
                     }
-                },
-                "ntfs" => {
-                    match ntfs::download_ntfs_region(self.working_dir.clone(), &self.region) {
-                        Ok(file_path) => {
-                            let duration = started_at.elapsed().unwrap();
-                            self.events
-                                .push_back(Event::DownloadingComplete(file_path, duration));
-                        }
-                        Err(err) => {
-                            self.events.push_back(Event::DownloadingError(format!(
-                                "Could not download: {}",
-                                err
-                            )));
-                        }
-                    }
-                }
-                _ => {
-                    self.events.push_back(Event::DownloadingError(format!(
-                        "Dont know how to download {}",
-                        &self.data_source
-                    )));
                 }
-            },
+            }
             State::DownloadingError { details: _ } => {
                 // We can't stay in downloading error state, we need to go back to not available
                 // to terminate the fsm
                 // It might be the place to do some cleanup
-                self.events.push_back(Event::Reset);
+                events.push_back(Event::Reset);
             }
             State::Downloaded {
                 file_path,
                 duration: _,
             } => {
-                // We're done downloading, now we need an extra processing step for cosmogony
-                match self.data_source.as_ref() {
-                    "cosmogony" => {
-                        self.events.push_back(Event::Process(file_path.clone()));
-                    }
-                    _ => {
-                        self.events.push_back(Event::Index(file_path.clone()));
-                    }
+                // Whether downloaded or staged, this is the dataset we index.
+                job.track_inputs(&file_path);
+                // We're done downloading, now some data sources need an extra processing step
+                // (eg cosmogony, or GTFS which must be converted to NTFS).
+                if source.needs_processing(job) {
+                    events.push_back(Event::Process(file_path));
+                } else {
+                    events.push_back(Event::Index(file_path));
                 }
             }
             State::ProcessingInProgress {
                 file_path,
                 started_at,
-            } => match self.data_source.as_ref() {
-                "cosmogony" => {
-                    match cosmogony::generate_cosmogony(
-                        self.cosmogony_dir.clone(),
-                        self.working_dir.clone(),
-                        file_path.clone(),
-                        &self.region,
-                    ) {
-                        Ok(path) => {
-                            let duration = started_at.elapsed().unwrap();
-                            self.events
-                                .push_back(Event::ProcessingComplete(path, duration));
-                        }
-                        Err(err) => {
-                            self.events.push_back(Event::ProcessingError(format!(
-                                "Could not process: {}",
-                                err
-                            )));
-                        }
+                ..
+            } => {
+                job.log.set_step(logs::PROCESSING);
+                let mut reporter = Reporter::new(state, notifier);
+                let res = source.process(job, file_path, &mut reporter).await;
+                let duration = started_at.elapsed().unwrap();
+                job.track_step(logs::PROCESSING, res.as_ref().ok().map(|_| duration));
+                match res {
+                    Ok(path) => {
+                        events.push_back(Event::ProcessingComplete(path, duration));
+                    }
+                    Err(err @ error::Error::TimeoutError { .. }) => {
+                        events.push_back(Event::Timeout(
+                            String::from(logs::PROCESSING),
+                            err.to_string(),
+                        ));
+                    }
+                    Err(err) => {
+                        events.push_back(Event::ProcessingError(format!(
+                            "Could not process {}: {}",
+                            source.name(),
+                            err
+                        )));
                     }
                 }
-                _ => {
-                    self.events.push_back(Event::ProcessingError(format!(
-                        "Dont know how to process {}",
-                        &self.data_source
-                    )));
-                }
-            },
+            }
             State::ProcessingError { details: _ } => {
-                self.events.push_back(Event::Reset);
+                events.push_back(Event::Reset);
             }
             State::Processed {
                 file_path,
                 duration: _,
             } => {
-                self.events.push_back(Event::Index(file_path.clone()));
+                events.push_back(Event::Index(file_path));
             }
             State::IndexingInProgress {
                 file_path,
                 started_at,
+                ..
             } => {
-                match self.data_source.as_ref() {
-                    "bano" => {
-                        match bano::index_bano_region(
-                            self.mimirs_dir.clone(),
-                            self.es.clone(),
-                            file_path.clone(),
-                        ) {
-                            Ok(()) => {
-                                let duration = started_at.elapsed().unwrap();
-                                self.events.push_back(Event::IndexingComplete(duration));
-                            }
-                            Err(err) => {
-                                self.events.push_back(Event::IndexingError(format!(
-                                    "Could not index BANO: {}",
-                                    err
-                                )));
-                            }
-                        }
-                    }
-                    "osm" => {
-                        // We need to analyze the index_type to see how we are going to import
-                        // osm: do we need to import admins, streets, ...?
-                        // FIXME: Here, for simplicity, we hard code index_poi = false
-                        let index = match self.index_type.as_ref() {
-                            "admins" => Some((true, false, false)),
-                            "streets" => Some((false, true, false)),
-                            _ => None,
-                        };
-
-                        if index.is_none() {
-                            self.events.push_back(Event::IndexingError(format!(
-                                "Could not index {} using OSM",
-                                self.index_type
-                            )));
-                        } else {
-                            let index = index.unwrap();
-                            match osm::index_osm_region(
-                                self.mimirs_dir.clone(),
-                                self.es.clone(),
-                                file_path.clone(),
-                                index.0,
-                                index.1,
-                                index.2,
-                                8, // 8 = default city level
-                            ) {
-                                Ok(()) => {
-                                    let duration = started_at.elapsed().unwrap();
-                                    self.events.push_back(Event::IndexingComplete(duration));
-                                }
-                                Err(err) => {
-                                    self.events.push_back(Event::IndexingError(format!(
-                                        "Could not index OSM: {}",
-                                        err
-                                    )));
-                                }
-                            }
-                        }
-                    }
-                    "cosmogony" => {
-                        match cosmogony::index_cosmogony_region(
-                            self.mimirs_dir.clone(),
-                            self.es.clone(),
-                            file_path.clone(),
-                        ) {
-                            Ok(()) => {
-                                let duration = started_at.elapsed().unwrap();
-                                self.events.push_back(Event::IndexingComplete(duration));
-                            }
-                            Err(err) => {
-                                self.events.push_back(Event::IndexingError(format!(
-                                    "Could not index cosmogony: {}",
-                                    err
-                                )));
-                            }
-                        }
+                job.log.set_step(logs::INDEXING);
+                let mut reporter = Reporter::new(state, notifier);
+                let res = source.index(job, file_path, &mut reporter).await;
+                let duration = started_at.elapsed().unwrap();
+                job.track_step(logs::INDEXING, res.as_ref().ok().map(|_| duration));
+                match res {
+                    Ok(()) => {
+                        // What the tools told us about the indexing is kept with the index.
+                        job.record().progress = state.progress().cloned();
+                        events.push_back(Event::IndexingComplete(duration));
                     }
-                    "ntfs" => {
-                        match ntfs::index_ntfs_region(
-                            self.mimirs_dir.clone(),
-                            self.es.clone(),
-                            file_path.clone(),
-                        ) {
-                            Ok(()) => {
-                                let duration = started_at.elapsed().unwrap();
-                                self.events.push_back(Event::IndexingComplete(duration));
-                            }
-                            Err(err) => {
-                                self.events.push_back(Event::IndexingError(format!(
-                                    "Could not index NTFS: {}",
-                                    err
-                                )));
-                            }
-                        }
+                    Err(err @ error::Error::TimeoutError { .. }) => {
+                        events.push_back(Event::Timeout(
+                            String::from(logs::INDEXING),
+                            err.to_string(),
+                        ));
                     }
-                    _ => {
-                        self.events.push_back(Event::IndexingError(format!(
-                            "Dont know how to index {}",
-                            &self.data_source
+                    Err(err) => {
+                        events.push_back(Event::IndexingError(format!(
+                            "Could not index {}: {}",
+                            source.name(),
+                            err
                         )));
                     }
                 }
             }
             State::IndexingError { details: _ } => {
-                self.events.push_back(Event::Reset);
+                events.push_back(Event::Reset);
             }
             State::Indexed { duration: _ } => {
-                self.events.push_back(Event::Validate);
+                events.push_back(Event::Validate);
             }
             State::ValidationInProgress => {
-                std::thread::sleep(std::time::Duration::from_secs(1));
-                self.events.push_back(Event::ValidationComplete);
+                job.log.set_step(logs::VALIDATION);
+                let started_at = Instant::now();
+                let res: Result<(), error::Error> = async {
+                    source.validate(job).await?;
+                    // We record which elasticsearch index holds the data of the index.
+                    let alias = elasticsearch::alias(&job.index_type, job.dataset())?;
+                    let es_index = elasticsearch::resolve_alias(job.es.clone(), &alias).await?;
+                    let record = job.record();
+                    record.es_alias = Some(alias);
+                    record.es_index = Some(es_index);
+                    Ok(())
+                }
+                .await;
+                let duration = started_at.elapsed();
+                job.track_step(logs::VALIDATION, res.as_ref().ok().map(|_| duration));
+                match res {
+                    Ok(()) => {
+                        events.push_back(Event::ValidationComplete);
+                    }
+                    Err(err @ error::Error::TimeoutError { .. }) => {
+                        events.push_back(Event::Timeout(
+                            String::from(logs::VALIDATION),
+                            err.to_string(),
+                        ));
+                    }
+                    Err(err) => {
+                        events.push_back(Event::ValidationError(format!(
+                            "Could not validate: {}",
+                            err
+                        )));
+                    }
+                }
             }
             State::ValidationError { details: _ } => {
-                self.events.push_back(Event::Reset);
+                events.push_back(Event::Reset);
+            }
+            State::TimedOut { .. } => {
+                events.push_back(Event::Reset);
             }
             State::Available => {}
             State::Failure(_) => {}
@@ -482,30 +672,24 @@ impl FSM {
 }
 
 pub async fn exec(mut fsm: FSM) -> Result<(), error::Error> {
-    fsm.events.push_back(Event::Download);
+    let event = if fsm.wait.is_some() {
+        Event::Wait
+    } else {
+        fsm.start()
+    };
+    fsm.events.push_back(event);
     while let Some(event) = fsm.events.pop_front() {
         fsm.next(event).await;
-        let i = fsm.topic.clone();
-        let j = format!("{}", fsm.id);
-        let k = serde_json::to_string(&fsm.state).unwrap();
-        let msg = vec![&i, &j, &k]; // topic, index id, status
-        let msg: Vec<Message> = msg.into_iter().map(Message::from).collect();
-        let res: MultipartIter<_, _> = msg.into();
-        info!(
-            &fsm.logger,
-            "FSM publishing new state {} for index {}", k, j
-        );
-        fsm.publish.send(res).await.unwrap();
+        let record = fsm.job.record.take();
+        fsm.notifier.notify(&fsm.state, record).await;
         if let State::Failure(string) = &fsm.state {
-            println!("{}", string);
+            error!(fsm.logger, "{}", string);
             break;
         } else {
             fsm.run().await;
         }
     }
-    fsm.publish.close().await.context(error::ZMQSendError {
-        details: String::from("Could not close publishing endpoint"),
-    })
+    Ok(())
 }
 
 // TODO Move the following in a test
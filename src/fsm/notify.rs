@@ -0,0 +1,127 @@
+use async_zmq::{Message, MultipartIter, SinkExt};
+use slog::{info, warn, Logger};
+use snafu::ResultExt;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::{Progress, Record, State};
+use crate::error;
+use crate::settings::Zmq;
+
+type Publish = async_zmq::publish::Publish<std::vec::IntoIter<Message>, Message>;
+
+// The publishing endpoint, shared by all the FSMs, since only one socket can bind it.
+#[derive(Clone)]
+pub struct Publisher {
+    topic: String, // The topic we need to broadcast.
+    endpoint: String,
+    publish: Arc<Mutex<Publish>>,
+}
+
+impl Publisher {
+    pub fn new(zmq: &Zmq) -> Result<Self, error::Error> {
+        let endpoint = format!("tcp://{}:{}", zmq.host, zmq.port);
+        let publish = async_zmq::publish(&endpoint)
+            .context(error::ZMQSocketError {
+                details: format!("Could not publish on endpoint '{}'", &endpoint),
+            })?
+            .bind()
+            .context(error::ZMQError {
+                details: format!(
+                    "Could not bind socket for publication on endpoint '{}'",
+                    &endpoint
+                ),
+            })?;
+        Ok(Publisher {
+            topic: zmq.topic.clone(),
+            endpoint,
+            publish: Arc::new(Mutex::new(publish)),
+        })
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    // Publish a message made of the topic, followed by the given parts.
+    pub async fn send(&self, parts: Vec<String>) -> Result<(), error::Error> {
+        let msg: Vec<Message> = std::iter::once(self.topic.clone())
+            .chain(parts.into_iter())
+            .map(Message::from)
+            .collect();
+        let res: MultipartIter<_, _> = msg.into();
+        self.publish
+            .lock()
+            .await
+            .send(res)
+            .await
+            .context(error::ZMQSendError {
+                details: format!("Could not publish on topic '{}'", &self.topic),
+            })
+    }
+}
+
+impl fmt::Debug for Publisher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Publisher")
+            .field("topic", &self.topic)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+// Publishes the states of the FSM of an index.
+pub struct Notifier {
+    id: i32,              // Id of the index, used to identify the published notifications.
+    publisher: Publisher, // Where we broadcast
+    logger: Logger,
+}
+
+impl Notifier {
+    pub fn new(id: i32, publisher: Publisher, logger: Logger) -> Self {
+        Notifier {
+            id,
+            publisher,
+            logger,
+        }
+    }
+
+    // Publish the state, along with the record if there is anything new.
+    pub async fn notify(&mut self, state: &State, record: Option<Record>) {
+        let j = format!("{}", self.id);
+        let k = serde_json::to_string(state).unwrap();
+        let mut msg = vec![j.clone(), k.clone()]; // index id, status
+        if let Some(record) = record {
+            msg.push(serde_json::to_string(&record).unwrap()); // and, if there is something new, the record
+        }
+        info!(
+            &self.logger,
+            "FSM publishing new state {} for index {}", k, j
+        );
+        if let Err(err) = self.publisher.send(msg).await {
+            warn!(
+                &self.logger,
+                "FSM could not publish state for index {}: {}", j, err
+            );
+        }
+    }
+}
+
+// Given to data sources, so that they can publish how far they are in a step.
+pub struct Reporter<'a> {
+    state: &'a mut State,
+    notifier: &'a mut Notifier,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(state: &'a mut State, notifier: &'a mut Notifier) -> Self {
+        Reporter { state, notifier }
+    }
+
+    // Publish the current state again, with the given progress.
+    pub async fn progress(&mut self, progress: Progress) {
+        self.state.set_progress(progress);
+        self.notifier.notify(self.state, None).await;
+    }
+}
@@ -1,11 +1,17 @@
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 use std::path::PathBuf;
-use std::process::Command;
+use std::time::Duration;
 use url::Url;
 
 use super::download;
 use super::error;
+use super::logs::Log;
+use super::options::Options;
+use super::tools::NTFS2MIMIR;
+use super::{DataSource, Job, Reporter};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct NTFSDownload {
@@ -24,8 +30,10 @@ struct NTFSFields {
     description: String,
     license: String,
     format: String,
-    validity_end_date: String,
-    validity_start_date: String,
+    #[serde(default)]
+    validity_end_date: Option<String>,
+    #[serde(default)]
+    validity_start_date: Option<String>,
     download: NTFSDownload,
     id: String,
     size: u32,
@@ -39,18 +47,56 @@ struct NTFSDataset {
     record_timestamp: String,
 }
 
-// Download the pbf associated with a region.
-// This is a very rudimentary function, which:
-// * does not handle correctly regions outside of france
-// * has a hard coded timeout to 300s
-// It will create a directory 'osm' inside the working directory (if not already present)
-// It will download a file
-pub fn download_ntfs_region(working_dir: PathBuf, region: &str) -> Result<PathBuf, error::Error> {
+// What we keep of an NTFS dataset, to store along the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NTFSMetadata {
+    pub dataset_id: String,
+    pub description: String,
+    pub license: String,
+    pub license_link: String,
+    pub update_date: String,
+    pub validity_start_date: Option<String>,
+    pub validity_end_date: Option<String>,
+}
+
+impl From<&NTFSDataset> for NTFSMetadata {
+    fn from(dataset: &NTFSDataset) -> Self {
+        NTFSMetadata {
+            dataset_id: dataset.datasetid.clone(),
+            description: dataset.fields.description.clone(),
+            license: dataset.fields.license.clone(),
+            license_link: dataset.fields.license_link.clone(),
+            update_date: dataset.fields.update_date.clone(),
+            validity_start_date: dataset.fields.validity_start_date.clone(),
+            validity_end_date: dataset.fields.validity_end_date.clone(),
+        }
+    }
+}
+
+// Dates from opendatasoft are either 'YYYY-MM-DD', or a full timestamp starting with it.
+fn parse_date(date: &str) -> Result<NaiveDate, error::Error> {
+    NaiveDate::parse_from_str(date.get(..10).unwrap_or(date), "%Y-%m-%d").map_err(|err| {
+        error::Error::MiscError {
+            details: format!("Could not parse date '{}': {}", date, err),
+        }
+    })
+}
+
+// Download the NTFS associated with a region, the region being the id of an opendatasoft dataset.
+// It will create a directory 'ntfs/<region>' inside the working directory (if not already
+// present), and extract the NTFS there.
+// Along with the path, it returns the metadata of the dataset.
+// Datasets that are no longer valid (their validity_end_date is past) are refused.
+pub fn download_ntfs_region(
+    working_dir: PathBuf,
+    region: &str,
+    timeout: Option<Duration>,
+) -> Result<(PathBuf, NTFSMetadata), error::Error> {
     // For NTFS, the download is a bit more involved.
     // We need to download a first file, which describe the available datasets.
     // So we download the file in json format, and use serde to get a list of datasets.
-    // We filter that list to get the 'NTFS' dataset, and extract the id which is used to generate
-    // the URL from which we can download the data.
+    // We filter that list to get the 'NTFS' datasets still valid, pick the most recent one,
+    // and extract the id which is used to generate the URL from which we can download the data.
     // Finally we download the dataset, which is a zip, so we call unzip to extract the data.
     let target = format!(
         "https://navitia.opendatasoft.com/explore/dataset/{}/download/?format=json",
@@ -67,7 +113,7 @@ pub fn download_ntfs_region(working_dir: PathBuf, region: &str) -> Result<PathBu
             ),
         })?;
     }
-    let res = download::download(&target, filepath.clone())?;
+    let res = download::download(&target, filepath.clone(), timeout)?;
     let datasets = std::fs::read_to_string(&res.0).context(error::IOError {
         details: format!(
             "Could not read content of NTFS first download {}",
@@ -78,74 +124,143 @@ pub fn download_ntfs_region(working_dir: PathBuf, region: &str) -> Result<PathBu
         serde_json::from_str(&datasets).context(error::SerdeJSONError {
             details: "Could not deserialize NTFS datasets",
         })?;
-    let url = datasets
+    let today = Utc::today().naive_utc();
+    let ntfs = datasets
         .iter()
-        .find_map(|dataset| {
-            if dataset.fields.format == "NTFS" {
-                Some(format!(
-                    "https://navitia.opendatasoft.com/api/v2/catalog/datasets/fr-ne/files/{}",
-                    dataset.fields.download.id
-                ))
-            } else {
-                None
-            }
-        })
+        .filter(|dataset| dataset.fields.format == "NTFS")
+        .collect::<Vec<_>>();
+    if ntfs.is_empty() {
+        return Err(error::Error::MiscError {
+            details: format!("Could not find NTFS dataset for {}", region),
+        });
+    }
+    let mut valid = Vec::new();
+    for dataset in ntfs {
+        let expired = match &dataset.fields.validity_end_date {
+            Some(date) => parse_date(date)? < today,
+            None => false,
+        };
+        if !expired {
+            valid.push(dataset);
+        }
+    }
+    // Dates are formatted 'YYYY-MM-DD...', so we can compare them as strings.
+    let dataset = valid
+        .into_iter()
+        .max_by(|a, b| a.fields.update_date.cmp(&b.fields.update_date))
         .ok_or(error::Error::MiscError {
-            details: String::from("Could not find NTFS dataset"),
+            details: format!(
+                "The NTFS dataset for {} is no longer valid (validity_end_date is past)",
+                region
+            ),
         })?;
+    let url = format!(
+        "https://navitia.opendatasoft.com/api/v2/catalog/datasets/{}/files/{}",
+        dataset.datasetid, dataset.fields.download.id
+    );
+    let metadata = NTFSMetadata::from(dataset);
     // Note, that since we have the URL, we don't need the file returned by the previous
     // download... so bye bye
     std::fs::remove_file(res.0.as_path()).context(error::IOError {
         details: format!("Could not remove {}", res.0.display()),
     })?;
-    let res = download::download(&url, filepath.clone())?;
-    let mut command = Command::new("unzip");
-    // We want to unzip in the director 'filepath'
-    command.arg("-d").arg(filepath.clone());
-    // We want to overwrite files without prompting
-    command.arg("-o");
-    command.arg(res.0.as_path());
-    let output = command.output().context(error::IOError {
-        details: format!("Could not unzip {}", filepath.display()),
-    })?;
-    // Same thing, we don't need the zip file, so remove it.
-    std::fs::remove_file(res.0.as_path()).context(error::IOError {
-        details: format!("Could not remove {}", res.0.display()),
-    })?;
-    if !output.status.success() {
+    let res = download::download(&url, filepath.clone(), timeout)?;
+    download::unzip(res.0.as_path(), filepath.as_path())?;
+    Ok((filepath, metadata))
+}
+
+// Check that the region is the id of an opendatasoft dataset.
+pub async fn check_ntfs_region(region: &str) -> Result<(), error::Error> {
+    let target = format!(
+        "https://navitia.opendatasoft.com/api/v2/catalog/datasets/{}",
+        region
+    );
+    if download::exists(&target).await {
+        Ok(())
+    } else {
         Err(error::Error::MiscError {
-            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
+            details: format!("No NTFS dataset '{}' in the navitia.io catalog", region),
         })
-    } else {
-        Ok(filepath)
     }
 }
 
-pub fn index_ntfs_region(
+pub async fn index_ntfs_region(
     mimirs_dir: PathBuf,
     es: Url,
     filepath: PathBuf,
+    options: &Options,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
 ) -> Result<(), error::Error> {
     let mut execpath = mimirs_dir;
-    execpath.push("ntfs2mimir");
-    // FIXME Need test file exists
-    let mut command = Command::new(&execpath);
+    execpath.push(NTFS2MIMIR);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
     command
         .arg("--connection-string")
         .arg(es.as_str())
         .arg("--input")
-        .arg(filepath);
-    let output = command.output().context(error::IOError {
-        details: format!(
-            "Could not create ntfs2mimir command using {}",
-            execpath.display()
-        ),
-    })?;
-    if !output.status.success() {
-        Err(error::Error::MiscError {
-            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
+        .arg(filepath)
+        .args(options.args());
+    log.run(&mut command, NTFS2MIMIR, reporter).await
+}
+
+// NTFS datasets from the navitia.io catalog, indexed with ntfs2mimir
+pub struct Ntfs;
+
+#[async_trait]
+impl DataSource for Ntfs {
+    fn name(&self) -> &'static str {
+        "ntfs"
+    }
+
+    fn index_types(&self) -> &'static [&'static str] {
+        &["stops"]
+    }
+
+    fn tools(&self) -> &'static [&'static str] {
+        &[NTFS2MIMIR]
+    }
+
+    async fn check_region(&self, region: &str) -> Result<(), error::Error> {
+        check_ntfs_region(region).await
+    }
+
+    // The metadata of the dataset we picked is stored along with the index. The download blocks,
+    // so it runs on the blocking pool.
+    async fn download(
+        &self,
+        job: &mut Job,
+        _reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        let working_dir = job.working_dir.clone();
+        let region = job.region.clone();
+        let timeout = job.log.remaining();
+        let (file_path, metadata) = tokio::task::spawn_blocking(move || {
+            download_ntfs_region(working_dir, &region, timeout)
         })
-    } else {
-        Ok(())
+        .await
+        .context(error::TokioJoinError {
+            details: format!("Could not download NTFS for region {}", job.region),
+        })??;
+        job.record().metadata = serde_json::to_value(metadata).ok();
+        Ok(file_path)
+    }
+
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        index_ntfs_region(
+            job.mimirs_dir.clone(),
+            job.es.clone(),
+            file_path,
+            &job.options,
+            &job.log,
+            reporter,
+        )
+        .await
     }
 }
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+use url::Url;
+
+use super::error;
+use super::logs::Log;
+use super::options::Options;
+use super::tools::OPENADDRESSES2MIMIR;
+use super::Reporter;
+
+pub async fn index_openaddresses_region(
+    mimirs_dir: PathBuf,
+    es: Url,
+    filepath: PathBuf,
+    options: &Options,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
+) -> Result<(), error::Error> {
+    let mut execpath = mimirs_dir;
+    execpath.push(OPENADDRESSES2MIMIR);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
+    command
+        .arg("--connection-string")
+        .arg(es.as_str())
+        .arg("--input")
+        .arg(filepath)
+        .args(options.args());
+    log.run(&mut command, OPENADDRESSES2MIMIR, reporter).await
+}
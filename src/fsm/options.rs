@@ -0,0 +1,324 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use super::error;
+use super::DataSource;
+
+// The names of the options, as given in requests.
+pub const CITY_LEVEL: &str = "city_level";
+pub const SHARDS: &str = "shards";
+pub const REPLICAS: &str = "replicas";
+pub const THREADS: &str = "threads";
+pub const DATASET: &str = "dataset";
+pub const CONFIG_DIR: &str = "config_dir";
+
+// Tuning parameters of the XXX2mimir tools, given with an index request. Whatever is not given
+// is left to the tools' defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Options {
+    // The admin level of cities, for osm2mimir
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city_level: Option<u32>,
+    // The number of shards of the elasticsearch index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shards: Option<u32>,
+    // The number of replicas of the elasticsearch index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replicas: Option<u32>,
+    // The number of threads used to index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+    // The mimirsbrunn dataset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>,
+    // Where the mimirsbrunn configuration is found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_dir: Option<PathBuf>,
+}
+
+impl Options {
+    // Parse the options given as (name, value) pairs, making sure the data source supports them.
+    // A configuration directory must be inside 'config_root'.
+    pub fn parse(
+        source: &dyn DataSource,
+        pairs: Vec<(String, String)>,
+        config_root: &str,
+    ) -> Result<Self, error::Error> {
+        let mut options = Options::default();
+        for (name, value) in pairs {
+            if !source.options().contains(&name.as_str()) {
+                return Err(error::Error::MiscError {
+                    details: format!(
+                        "Option '{}' is not supported by {}, expected one of {}",
+                        name,
+                        source.name(),
+                        source.options().join(", ")
+                    ),
+                });
+            }
+            match name.as_str() {
+                CITY_LEVEL => options.city_level = Some(parse_number(&name, &value, 1, 11)?),
+                SHARDS => options.shards = Some(parse_number(&name, &value, 1, 1024)?),
+                REPLICAS => options.replicas = Some(parse_number(&name, &value, 0, 16)?),
+                THREADS => options.threads = Some(parse_number(&name, &value, 1, 256)?),
+                DATASET => options.dataset = Some(parse_dataset(&value)?),
+                CONFIG_DIR => options.config_dir = Some(parse_config_dir(config_root, &value)?),
+                _ => {
+                    return Err(error::Error::MiscError {
+                        details: format!("Unknown option '{}'", name),
+                    });
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    // The command line arguments for the XXX2mimir tools, using the given flags for the
+    // number of shards and replicas (osm2mimir has one for each type of object).
+    pub fn args_with(&self, shards_flag: &str, replicas_flag: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(dataset) = &self.dataset {
+            args.push(String::from("--dataset"));
+            args.push(dataset.clone());
+        }
+        if let Some(shards) = self.shards {
+            args.push(String::from(shards_flag));
+            args.push(shards.to_string());
+        }
+        if let Some(replicas) = self.replicas {
+            args.push(String::from(replicas_flag));
+            args.push(replicas.to_string());
+        }
+        if let Some(threads) = self.threads {
+            args.push(String::from("--nb-threads"));
+            args.push(threads.to_string());
+        }
+        if let Some(config_dir) = &self.config_dir {
+            args.push(String::from("--config-dir"));
+            args.push(config_dir.display().to_string());
+        }
+        args
+    }
+
+    pub fn args(&self) -> Vec<String> {
+        self.args_with("--nb-shards", "--nb-replicas")
+    }
+}
+
+fn parse_number(name: &str, value: &str, min: u32, max: u32) -> Result<u32, error::Error> {
+    match value.parse::<u32>() {
+        Ok(n) if (min..=max).contains(&n) => Ok(n),
+        _ => Err(error::Error::MiscError {
+            details: format!(
+                "Option '{}' must be a number between {} and {}, not '{}'",
+                name, min, max, value
+            ),
+        }),
+    }
+}
+
+// Dataset names end up in elasticsearch index names, which must be lowercase.
+fn parse_dataset(value: &str) -> Result<String, error::Error> {
+    let valid = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if valid {
+        Ok(String::from(value))
+    } else {
+        Err(error::Error::MiscError {
+            details: format!(
+                "Option '{}' must be made of lowercase letters, digits, '_' and '-', not '{}'",
+                DATASET, value
+            ),
+        })
+    }
+}
+
+// Relative configuration directories are taken relative to the configuration root. We refuse
+// anything that, once canonicalized (ie symbolic links and '..' are resolved), is not inside
+// it, so that an API user cannot hand the tools an arbitrary directory.
+fn parse_config_dir(config_root: &str, value: &str) -> Result<PathBuf, error::Error> {
+    let root = fs::canonicalize(config_root).map_err(|err| error::Error::MiscError {
+        details: format!(
+            "Could not resolve configuration root {}: {}",
+            config_root, err
+        ),
+    })?;
+    let config_dir = fs::canonicalize(root.join(value)).map_err(|err| error::Error::MiscError {
+        details: format!("Option '{}': could not find {}: {}", CONFIG_DIR, value, err),
+    })?;
+    if !config_dir.starts_with(&root) {
+        return Err(error::Error::MiscError {
+            details: format!(
+                "Option '{}': {} is not inside {}",
+                CONFIG_DIR,
+                config_dir.display(),
+                root.display()
+            ),
+        });
+    }
+    if !config_dir.is_dir() {
+        return Err(error::Error::MiscError {
+            details: format!(
+                "Option '{}': {} is not a directory",
+                CONFIG_DIR,
+                config_dir.display()
+            ),
+        });
+    }
+    Ok(config_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bano::Bano;
+    use super::*;
+
+    fn parse_in(config_root: &str, pairs: &[(&str, &str)]) -> Result<Options, error::Error> {
+        let pairs = pairs
+            .iter()
+            .map(|(name, value)| (String::from(*name), String::from(*value)))
+            .collect();
+        Options::parse(&Bano { concurrency: 1 }, pairs, config_root)
+    }
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<Options, error::Error> {
+        parse_in("/no/such/config/root", pairs)
+    }
+
+    #[test]
+    fn parses_the_options_of_the_data_source() {
+        let options = parse(&[
+            (SHARDS, "3"),
+            (REPLICAS, "0"),
+            (THREADS, "4"),
+            (DATASET, "fr_ile-de-france"),
+        ])
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                shards: Some(3),
+                replicas: Some(0),
+                threads: Some(4),
+                dataset: Some(String::from("fr_ile-de-france")),
+                ..Options::default()
+            }
+        );
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+    }
+
+    #[test]
+    fn refuses_options_the_data_source_does_not_support() {
+        // BANO has no cities.
+        assert!(parse(&[(CITY_LEVEL, "8")]).is_err());
+        assert!(parse(&[("color", "blue")]).is_err());
+    }
+
+    #[test]
+    fn refuses_numbers_out_of_range() {
+        for (name, value) in &[
+            (SHARDS, "0"),
+            (SHARDS, "1025"),
+            (REPLICAS, "17"),
+            (REPLICAS, "-1"),
+            (THREADS, "0"),
+            (THREADS, "four"),
+        ] {
+            assert!(
+                parse(&[(*name, *value)]).is_err(),
+                "{} = '{}' was accepted",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_datasets_unfit_for_elasticsearch() {
+        for value in &["", "FR", "fr idf", "fr/idf", "fr.idf"] {
+            assert!(
+                parse(&[(DATASET, *value)]).is_err(),
+                "dataset '{}' was accepted",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_config_dirs_inside_the_config_root() {
+        let base = std::env::temp_dir().join(format!(
+            "mimir_ingest_options_inside_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(base.join("config/fr")).unwrap();
+        let root = base.join("config").display().to_string();
+        let expected = fs::canonicalize(base.join("config/fr")).unwrap();
+
+        let options = parse_in(&root, &[(CONFIG_DIR, "fr")]).unwrap();
+        assert_eq!(options.config_dir.as_ref(), Some(&expected));
+        let absolute = expected.display().to_string();
+        let options = parse_in(&root, &[(CONFIG_DIR, absolute.as_str())]).unwrap();
+        assert_eq!(options.config_dir, Some(expected));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn refuses_config_dirs_outside_the_config_root() {
+        let base = std::env::temp_dir().join(format!(
+            "mimir_ingest_options_outside_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(base.join("config")).unwrap();
+        fs::create_dir_all(base.join("elsewhere")).unwrap();
+        fs::write(base.join("config/mimir.toml"), "").unwrap();
+        let root = base.join("config").display().to_string();
+        let elsewhere = base.join("elsewhere").display().to_string();
+
+        for value in &[
+            elsewhere.as_str(),
+            "../elsewhere",
+            "/",
+            "mimir.toml",
+            "missing",
+        ] {
+            assert!(
+                parse_in(&root, &[(CONFIG_DIR, *value)]).is_err(),
+                "config dir '{}' was accepted",
+                value
+            );
+        }
+        // Without a config root, there is no config dir.
+        assert!(parse(&[(CONFIG_DIR, "fr")]).is_err());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn gives_the_options_as_arguments() {
+        let options = Options {
+            shards: Some(3),
+            replicas: Some(1),
+            dataset: Some(String::from("fr")),
+            ..Options::default()
+        };
+        assert_eq!(
+            options.args(),
+            vec!["--dataset", "fr", "--nb-shards", "3", "--nb-replicas", "1"]
+        );
+        assert_eq!(
+            options.args_with("--nb-admin-shards", "--nb-admin-replicas"),
+            vec![
+                "--dataset",
+                "fr",
+                "--nb-admin-shards",
+                "3",
+                "--nb-admin-replicas",
+                "1"
+            ]
+        );
+    }
+}
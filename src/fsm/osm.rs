@@ -1,20 +1,29 @@
+use async_trait::async_trait;
 use snafu::ResultExt;
 use std::path::PathBuf;
-use std::process::Command;
+use std::time::Duration;
 use url::Url;
 
+use super::artifacts::{Artifacts, Lease};
 use super::download;
 use super::error;
+use super::logs::Log;
+use super::options::{Options, CITY_LEVEL, CONFIG_DIR, DATASET, REPLICAS, SHARDS, THREADS};
+use super::tools::OSM2MIMIR;
+use super::{DataSource, Job, Reporter};
 
 // Download the pbf associated with a region.
 // This is a very rudimentary function, which:
 // * does not handle correctly regions outside of france
-// * has a hard coded timeout to 300s
+// * gives up after 'timeout', if any
 // It will create a directory 'osm' inside the working directory (if not already present)
 // It will download a file
-pub fn download_osm_region(working_dir: PathBuf, region: &str) -> Result<PathBuf, error::Error> {
-    let filename = format!("{}-latest.osm.pbf", region);
-    let target = format!("https://download.geofabrik.de/europe/france/{}", filename);
+pub fn download_osm_region(
+    working_dir: PathBuf,
+    region: &str,
+    timeout: Option<Duration>,
+) -> Result<PathBuf, error::Error> {
+    let target = osm_region_url(region);
     let mut filepath = working_dir;
     filepath.push("osm");
     if !filepath.is_dir() {
@@ -25,23 +34,70 @@ pub fn download_osm_region(working_dir: PathBuf, region: &str) -> Result<PathBuf
             ),
         })?;
     }
-    let res = download::download(&target, filepath)?;
+    let res = download::download(&target, filepath, timeout)?;
     Ok(res.0)
 }
 
-pub fn index_osm_region(
+// Where geofabrik has the PBF of a region.
+fn osm_region_url(region: &str) -> String {
+    format!(
+        "https://download.geofabrik.de/europe/france/{}-latest.osm.pbf",
+        region
+    )
+}
+
+// Check that geofabrik has a PBF for the region.
+pub async fn check_osm_region(region: &str) -> Result<(), error::Error> {
+    let target = osm_region_url(region);
+    if download::exists(&target).await {
+        Ok(())
+    } else {
+        Err(error::Error::MiscError {
+            details: format!("No OSM extract for region '{}' at {}", region, target),
+        })
+    }
+}
+
+// Get a lease on the PBF of a region, shared by all the jobs which need it, so that it is
+// downloaded only once.
+pub async fn fetch_osm_region(
+    artifacts: &Artifacts,
+    working_dir: PathBuf,
+    region: &str,
+    timeout: Option<Duration>,
+) -> Result<Lease, error::Error> {
+    let owned = String::from(region);
+    artifacts
+        .fetch(&format!("osm/{}", region), move || {
+            download_osm_region(working_dir, &owned, timeout)
+        })
+        .await
+}
+
+// Which objects osm2mimir needs to import for a given index type, as a tuple
+// (admin, way, poi), or None if we can't produce that index type from OSM.
+pub fn import_flags(index_type: &str) -> Option<(bool, bool, bool)> {
+    match index_type {
+        "admins" => Some((true, false, false)),
+        "streets" => Some((false, true, false)),
+        "pois" => Some((false, false, true)),
+        _ => None,
+    }
+}
+
+pub async fn index_osm_region(
     mimirs_dir: PathBuf,
     es: Url,
     filepath: PathBuf, // osm pbf
-    admin: bool,
-    way: bool,
-    poi: bool,
-    city_level: u32,
+    (admin, way, poi): (bool, bool, bool),
+    options: &Options,
+    log: &Log,
+    reporter: &mut Reporter<'_>,
 ) -> Result<(), error::Error> {
     let mut execpath = mimirs_dir;
-    execpath.push("osm2mimir");
-    // FIXME Need test file exists
-    let mut command = Command::new(&execpath);
+    execpath.push(OSM2MIMIR);
+    // The tool has been checked when the data source was registered.
+    let mut command = log.command(&execpath);
     command
         .arg("--connection-string")
         .arg(es.as_str())
@@ -56,18 +112,87 @@ pub fn index_osm_region(
     if poi {
         command.arg("--import-poi");
     }
+    // 8 = default city level
+    let city_level = options.city_level.unwrap_or(8);
     command.arg("--city-level").arg(city_level.to_string());
-    let output = command.output().context(error::IOError {
-        details: format!(
-            "Could not create osm2mimir command using {}",
-            execpath.display()
-        ),
-    })?;
-    if !output.status.success() {
-        Err(error::Error::MiscError {
-            details: format!("=> {}", String::from_utf8(output.stderr).unwrap()),
-        })
-    } else {
-        Ok(())
+    // osm2mimir creates one index for each type of object, each with its own settings.
+    let (shards_flag, replicas_flag) = match (admin, way, poi) {
+        (true, _, _) => ("--nb-admin-shards", "--nb-admin-replicas"),
+        (_, true, _) => ("--nb-way-shards", "--nb-way-replicas"),
+        _ => ("--nb-poi-shards", "--nb-poi-replicas"),
+    };
+    command.args(options.args_with(shards_flag, replicas_flag));
+    log.run(&mut command, OSM2MIMIR, reporter).await
+}
+
+// OpenStreetMap, indexed with osm2mimir
+pub struct Osm {
+    pub artifacts: Artifacts, // Where we get the PBF from, shared with the other jobs
+}
+
+#[async_trait]
+impl DataSource for Osm {
+    fn name(&self) -> &'static str {
+        "osm"
+    }
+
+    fn index_types(&self) -> &'static [&'static str] {
+        &["admins", "streets", "pois"]
+    }
+
+    fn tools(&self) -> &'static [&'static str] {
+        &[OSM2MIMIR]
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        &[CITY_LEVEL, SHARDS, REPLICAS, THREADS, DATASET, CONFIG_DIR]
+    }
+
+    fn uses_osm_pbf(&self) -> bool {
+        true
+    }
+
+    async fn check_region(&self, region: &str) -> Result<(), error::Error> {
+        check_osm_region(region).await
+    }
+
+    async fn download(
+        &self,
+        job: &mut Job,
+        _reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        let lease = fetch_osm_region(
+            &self.artifacts,
+            job.working_dir.clone(),
+            &job.region,
+            job.log.remaining(),
+        )
+        .await?;
+        let file_path = lease.path().to_path_buf();
+        job.leases.push(lease);
+        Ok(file_path)
+    }
+
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        // We need to analyze the index_type to see how we are going to import
+        // osm: do we need to import admins, streets, ...?
+        let flags = import_flags(&job.index_type).ok_or(error::Error::MiscError {
+            details: format!("Could not index {} using OSM", job.index_type),
+        })?;
+        index_osm_region(
+            job.mimirs_dir.clone(),
+            job.es.clone(),
+            file_path,
+            flags,
+            &job.options,
+            &job.log,
+            reporter,
+        )
+        .await
     }
 }
@@ -0,0 +1,180 @@
+use super::tools::{
+    BANO2MIMIR, COSMOGONY, COSMOGONY2MIMIR, NTFS2MIMIR, OPENADDRESSES2MIMIR, OSM2MIMIR,
+};
+use super::Progress;
+
+// Turns the output of a tool into progress, line by line. Only changes worth publishing are
+// returned, so that a chatty tool does not flood the notifications.
+pub trait Parser: Send {
+    fn parse(&mut self, line: &str) -> Option<Progress>;
+}
+
+// The parser for the output of a tool, given its name.
+pub fn parser(tool: &str) -> Box<dyn Parser> {
+    match tool {
+        OSM2MIMIR | BANO2MIMIR | OPENADDRESSES2MIMIR | COSMOGONY2MIMIR | NTFS2MIMIR | COSMOGONY => {
+            Box::new(Mimirsbrunn::default())
+        }
+        _ => Box::new(Silent),
+    }
+}
+
+// For the tools we can't make sense of.
+struct Silent;
+
+impl Parser for Silent {
+    fn parse(&mut self, _line: &str) -> Option<Progress> {
+        None
+    }
+}
+
+// The mimirsbrunn tools (and cosmogony) log what they are doing, eg
+//   'importing streets into Mimir', or 'Extracting pois from osm'
+// how many objects they indexed once done with a type of object, eg
+//   'Nb of indexed street: 123456'
+// and sometimes a percentage, eg 'Reading: 42%'.
+#[derive(Default)]
+struct Mimirsbrunn {
+    phase: String,
+    count: u64,
+    percentage: Option<f64>,
+}
+
+// The messages telling the phase the tool is in.
+const PHASES: &[&str] = &["importing ", "Importing ", "Extracting ", "Reading "];
+
+// The message telling how many objects were indexed.
+const INDEXED: &str = "Nb of indexed ";
+
+impl Mimirsbrunn {
+    fn progress(&self) -> Progress {
+        Progress {
+            phase: self.phase.clone(),
+            count: self.count,
+            total: None,
+            percentage: self.percentage,
+        }
+    }
+}
+
+impl Parser for Mimirsbrunn {
+    fn parse(&mut self, line: &str) -> Option<Progress> {
+        if let Some(pos) = line.find(INDEXED) {
+            let rest = &line[pos + INDEXED.len()..];
+            let mut parts = rest.splitn(2, ':');
+            let kind = parts.next()?.trim();
+            let count = parts
+                .next()?
+                .trim()
+                .split(|c: char| !c.is_ascii_digit())
+                .next()?
+                .parse::<u64>()
+                .ok()?;
+            self.phase = format!("Indexed {}", kind);
+            self.count = count;
+            self.percentage = Some(100.0);
+            return Some(self.progress());
+        }
+        if let Some(pos) = PHASES.iter().filter_map(|phase| line.find(phase)).min() {
+            // slog appends the key/values after a comma.
+            let phase = line[pos..].split(',').next().unwrap_or_default().trim();
+            if phase == self.phase {
+                return None;
+            }
+            self.phase = String::from(phase);
+            self.percentage = None;
+            return Some(self.progress());
+        }
+        if let Some(percentage) = percentage(line) {
+            // We only publish whole percents.
+            if self.percentage.map(f64::floor) == Some(percentage.floor()) {
+                return None;
+            }
+            self.percentage = Some(percentage);
+            return Some(self.progress());
+        }
+        None
+    }
+}
+
+// The first word of the line which reads as a percentage, eg '42%' or '42.5%'.
+fn percentage(line: &str) -> Option<f64> {
+    line.split_whitespace()
+        .filter_map(|word| {
+            word.trim_end_matches(|c| c == ',' || c == ')')
+                .strip_suffix('%')?
+                .parse::<f64>()
+                .ok()
+        })
+        .find(|p| (0.0..=100.0).contains(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(phase: &str, count: u64, percentage: Option<f64>) -> Option<Progress> {
+        Some(Progress {
+            phase: String::from(phase),
+            count,
+            total: None,
+            percentage,
+        })
+    }
+
+    #[test]
+    fn follows_the_phases_of_the_tool() {
+        let mut parser = parser(OSM2MIMIR);
+        assert_eq!(
+            parser
+                .parse("Oct 18 12:00:00.000 INFO importing streets into Mimir, module: osm2mimir"),
+            progress("importing streets into Mimir", 0, None)
+        );
+        // The same phase again is not worth publishing.
+        assert_eq!(
+            parser.parse("Oct 18 12:00:01.000 INFO importing streets into Mimir"),
+            None
+        );
+        assert_eq!(
+            parser.parse("Extracting pois from osm"),
+            progress("Extracting pois from osm", 0, None)
+        );
+    }
+
+    #[test]
+    fn counts_the_indexed_objects() {
+        let mut parser = parser(BANO2MIMIR);
+        assert_eq!(
+            parser.parse("INFO Nb of indexed addr: 123456, module: bano2mimir"),
+            progress("Indexed addr", 123_456, Some(100.0))
+        );
+        assert_eq!(parser.parse("INFO Nb of indexed addr: many"), None);
+    }
+
+    #[test]
+    fn publishes_whole_percents_only() {
+        let mut parser = parser(COSMOGONY);
+        assert_eq!(
+            parser.parse("Reading osm file"),
+            progress("Reading osm file", 0, None)
+        );
+        assert_eq!(
+            parser.parse("done: 42.5%"),
+            progress("Reading osm file", 0, Some(42.5))
+        );
+        assert_eq!(parser.parse("done: 42.9%"), None);
+        assert_eq!(
+            parser.parse("done: 43%, eta 2s"),
+            progress("Reading osm file", 0, Some(43.0))
+        );
+        // Not a percentage of the work.
+        assert_eq!(parser.parse("memory at 250%"), None);
+    }
+
+    #[test]
+    fn ignores_the_output_of_other_tools() {
+        let mut parser = parser("gtfs2ntfs");
+        assert_eq!(parser.parse("Nb of indexed stop: 12"), None);
+        assert_eq!(parser.parse("Reading: 42%"), None);
+    }
+}
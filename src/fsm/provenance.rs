@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use super::error;
+
+// The records of the downloads of a directory 'dir' are kept in '<dir>.provenance', rather
+// than next to the files, which the tools may read as a whole directory.
+const RECORDS: &str = "provenance";
+
+// How an index was produced: from which datasets, with which tools, and how long it took.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Provenance {
+    #[serde(default)]
+    pub inputs: Vec<Input>,
+    #[serde(default)]
+    pub commands: Vec<CommandRun>,
+    #[serde(default)]
+    pub steps: Vec<StepRun>,
+}
+
+// A dataset we indexed.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Input {
+    pub url: Option<String>, // None for a dataset staged on disk
+    pub file: PathBuf,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub last_modified: Option<String>, // As told by the server
+}
+
+// A tool we ran.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CommandRun {
+    pub step: String,
+    pub tool: String,
+    pub version: Option<String>,
+    pub command_line: String,
+    pub duration: Duration,
+    pub success: bool,
+}
+
+// A step of the FSM we completed.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StepRun {
+    pub step: String,
+    pub duration: Duration,
+}
+
+// The directory holding the records of the downloads of 'dir'.
+fn records_dir(dir: &Path) -> PathBuf {
+    let mut name = dir.as_os_str().to_owned();
+    name.push(".");
+    name.push(RECORDS);
+    PathBuf::from(name)
+}
+
+// The records of the downloads of 'dir' with a name starting with 'prefix'.
+fn records(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    fs::read_dir(records_dir(dir))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .map(|name| name.starts_with(prefix) && name.ends_with(".json"))
+                        .unwrap_or(false)
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default()
+}
+
+// Remember where a downloaded file comes from. The record survives the file being unzipped,
+// and is found again when the download is reused.
+pub fn record_download(
+    url: &str,
+    file: &Path,
+    last_modified: Option<String>,
+) -> Result<(), error::Error> {
+    let input = Input {
+        url: Some(String::from(url)),
+        file: file.to_path_buf(),
+        size: file_size(file),
+        sha256: sha256(file),
+        last_modified,
+    };
+    let (dir, name) = match (file.parent(), file.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => {
+            return Err(error::Error::MiscError {
+                details: format!("Could not record provenance of {}", file.display()),
+            })
+        }
+    };
+    let dir = records_dir(dir);
+    fs::create_dir_all(&dir).context(error::IOError {
+        details: format!("Could not create provenance directory {}", dir.display()),
+    })?;
+    let path = dir.join(format!("{}.json", name.to_string_lossy()));
+    let json = serde_json::to_string(&input).context(error::SerdeJSONError {
+        details: format!("Could not serialize provenance of {}", file.display()),
+    })?;
+    fs::write(&path, json).context(error::IOError {
+        details: format!("Could not write provenance {}", path.display()),
+    })
+}
+
+// Where the dataset at 'path' comes from: the records of the downloads it is made of, that is
+// the file itself, the archive it was extracted from, or the files of a directory. A dataset we
+// did not download (eg staged) is described as is.
+pub fn inputs(path: &Path) -> Vec<Input> {
+    let mut paths = if path.is_dir() {
+        records(path, "")
+    } else {
+        Vec::new()
+    };
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        // 'name' may be the archive 'name.zip' extracted in the directory 'name'.
+        paths.extend(records(dir, &format!("{}.", name)));
+    }
+    let mut inputs = paths
+        .iter()
+        .filter_map(|record| fs::read_to_string(record).ok())
+        .filter_map(|json| serde_json::from_str::<Input>(&json).ok())
+        .collect::<Vec<_>>();
+    if inputs.is_empty() {
+        let is_file = path.is_file();
+        inputs.push(Input {
+            url: None,
+            file: path.to_path_buf(),
+            size: file_size(path),
+            sha256: if is_file { sha256(path) } else { None },
+            last_modified: None,
+        });
+    }
+    inputs.sort_by(|a, b| a.file.cmp(&b.file));
+    inputs
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// The checksum of the file, as computed by sha256sum.
+fn sha256(path: &Path) -> Option<String> {
+    let output = Command::new("sha256sum").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .map(String::from)
+}
+
+// Remove a downloaded file (or the directory it was extracted in), along with the records of
+// where it comes from.
+pub fn remove_download(path: &Path) -> Result<(), error::Error> {
+    let is_dir = path.is_dir();
+    let mut paths = if is_dir {
+        records(path, "")
+    } else {
+        Vec::new()
+    };
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        paths.extend(records(dir, &format!("{}.", name)));
+    }
+    let res = if is_dir {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    res.context(error::IOError {
+        details: format!("Could not remove {}", path.display()),
+    })?;
+    for record in paths {
+        fs::remove_file(&record).context(error::IOError {
+            details: format!("Could not remove provenance {}", record.display()),
+        })?;
+    }
+    if is_dir {
+        // Nothing is recorded in there anymore.
+        let _ = fs::remove_dir(records_dir(path));
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use snafu::ResultExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
+
+use super::download;
+use super::error;
+use super::format::Format;
+use super::options::{CITY_LEVEL, CONFIG_DIR, DATASET, REPLICAS, SHARDS, THREADS};
+use super::tools::{
+    BANO2MIMIR, COSMOGONY2MIMIR, GTFS2NTFS, NTFS2MIMIR, OPENADDRESSES2MIMIR, OSM2MIMIR,
+};
+use super::{bano, cosmogony, gtfs, ntfs, openaddresses, osm};
+use super::{DataSource, Job, Reporter};
+
+// Download a dataset given by an arbitrary URL.
+// It will create a directory 'remote/<format>' inside the working directory (if not already
+// present), and download the file there. Archives (eg NTFS, GTFS) are extracted in a directory
+// named after the archive, and that directory is returned instead of the archive.
+pub fn download_remote(
+    working_dir: PathBuf,
+    url: &Url,
+    format: Format,
+    timeout: Option<Duration>,
+) -> Result<PathBuf, error::Error> {
+    let mut filepath = working_dir;
+    filepath.push("remote");
+    filepath.push(format.to_string());
+    if !filepath.is_dir() {
+        std::fs::create_dir_all(filepath.as_path()).context(error::IOError {
+            details: format!(
+                "Expected to download {} file in {}, which is not a directory",
+                format,
+                filepath.display()
+            ),
+        })?;
+    }
+    let res = download::download(url.as_str(), filepath, timeout)?;
+    if format.is_archive() {
+        let mut dir = res.0.with_extension("");
+        if dir == res.0 {
+            dir.set_extension("data");
+        }
+        download::unzip(res.0.as_path(), dir.as_path())?;
+        Ok(dir)
+    } else {
+        Ok(res.0)
+    }
+}
+
+// A dataset given by URL, routed to the XXX2mimir tool matching its declared format
+pub struct Remote;
+
+impl Remote {
+    fn format(job: &Job) -> Result<Format, error::Error> {
+        job.format.ok_or(error::Error::MiscError {
+            details: String::from("The url data source needs a format"),
+        })
+    }
+}
+
+#[async_trait]
+impl DataSource for Remote {
+    fn name(&self) -> &'static str {
+        "url"
+    }
+
+    // The options are those of the tool matching the format.
+    fn options(&self) -> &'static [&'static str] {
+        &[CITY_LEVEL, SHARDS, REPLICAS, THREADS, DATASET, CONFIG_DIR]
+    }
+
+    // The actual index types depend on the format, and are checked when the request is made.
+    fn index_types(&self) -> &'static [&'static str] {
+        &["admins", "streets", "addresses", "pois", "stops"]
+    }
+
+    // Which tool we need depends on the format, so we need them all.
+    fn tools(&self) -> &'static [&'static str] {
+        &[
+            OSM2MIMIR,
+            BANO2MIMIR,
+            OPENADDRESSES2MIMIR,
+            NTFS2MIMIR,
+            GTFS2NTFS,
+            COSMOGONY2MIMIR,
+        ]
+    }
+
+    // The download blocks, so it runs on the blocking pool.
+    async fn download(
+        &self,
+        job: &mut Job,
+        _reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        let url = job.url.clone().ok_or(error::Error::MiscError {
+            details: String::from("The url data source needs a URL"),
+        })?;
+        let format = Remote::format(job)?;
+        let working_dir = job.working_dir.clone();
+        let timeout = job.log.remaining();
+        let details = format!("Could not download {}", url);
+        tokio::task::spawn_blocking(move || download_remote(working_dir, &url, format, timeout))
+            .await
+            .context(error::TokioJoinError { details })?
+    }
+
+    // GTFS must be converted to NTFS.
+    fn needs_processing(&self, job: &Job) -> bool {
+        job.format == Some(Format::GtfsZip)
+    }
+
+    async fn process(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        gtfs::process_gtfs(job, file_path, reporter).await
+    }
+
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error> {
+        let mimirs_dir = job.mimirs_dir.clone();
+        let es = job.es.clone();
+        let options = &job.options;
+        let log = &job.log;
+        match Remote::format(job)? {
+            Format::OsmPbf => {
+                let flags = osm::import_flags(&job.index_type).ok_or(error::Error::MiscError {
+                    details: format!("Could not index {} using OSM", job.index_type),
+                })?;
+                osm::index_osm_region(mimirs_dir, es, file_path, flags, options, log, reporter)
+                    .await
+            }
+            Format::BanoCsv => {
+                bano::index_bano_region(mimirs_dir, es, file_path, options, log, reporter).await
+            }
+            Format::OpenaddressesCsv => {
+                openaddresses::index_openaddresses_region(
+                    mimirs_dir, es, file_path, options, log, reporter,
+                )
+                .await
+            }
+            // GTFS has been converted to NTFS during the processing step.
+            Format::NtfsZip | Format::GtfsZip => {
+                ntfs::index_ntfs_region(mimirs_dir, es, file_path, options, log, reporter).await
+            }
+            Format::CosmogonyJson => {
+                cosmogony::index_cosmogony_region(mimirs_dir, es, file_path, options, log, reporter)
+                    .await
+            }
+        }
+    }
+
+    async fn validate(&self, job: &mut Job) -> Result<(), error::Error> {
+        gtfs::validate_gtfs(job).await
+    }
+}
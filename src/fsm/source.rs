@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+use slog::{info, warn, Logger};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::artifacts::Artifacts;
+use super::options::{CONFIG_DIR, DATASET, REPLICAS, SHARDS};
+use super::tools::{check_tool, ToolCheck};
+use super::{bano, cosmogony, gtfs, ntfs, osm, remote};
+use super::{Job, Reporter};
+use crate::error;
+use crate::settings::Settings;
+
+// A provider of datasets, which knows how to download them, and how to index them.
+// The FSM drives a data source through each step:
+// download -> process (optional) -> index -> validate
+#[async_trait]
+pub trait DataSource: Send + Sync {
+    // The name used in requests, eg 'osm'
+    fn name(&self) -> &'static str;
+
+    // The types of index this data source can produce, eg 'admins', 'streets'
+    fn index_types(&self) -> &'static [&'static str];
+
+    // The external tools run by this data source (see tools), which must be installed for
+    // the data source to be available.
+    fn tools(&self) -> &'static [&'static str];
+
+    // The types of index which must be available for the region before we can index
+    // 'index_type': osm2mimir and bano2mimir attach streets and addresses to the admins
+    // already present in elasticsearch.
+    fn prerequisites(&self, index_type: &str) -> &'static [&'static str] {
+        match index_type {
+            "streets" | "addresses" => &["admins"],
+            _ => &[],
+        }
+    }
+
+    // The region of the prerequisites of an index of 'region'. Data sources which name their
+    // regions differently than those producing the prerequisites (eg BANO departments) map them,
+    // or return None if no region matches, in which case there is nothing to wait for.
+    fn prerequisites_region(&self, region: &str) -> Option<String> {
+        Some(String::from(region))
+    }
+
+    // The regions of the indexes created for a bundle of 'region', an OSM extract (eg
+    // 'ile-de-france'). Data sources naming their regions differently (eg BANO departments) map
+    // it to theirs, or return nothing if no region matches.
+    fn bundle_regions(&self, region: &str) -> Vec<String> {
+        vec![String::from(region)]
+    }
+
+    // The options (see Options) supported by the tools of this data source.
+    fn options(&self) -> &'static [&'static str] {
+        &[DATASET, SHARDS, REPLICAS, CONFIG_DIR]
+    }
+
+    // Whether the dataset is the OSM PBF of the region, which can then be downloaded once and
+    // shared with other indexes.
+    fn uses_osm_pbf(&self) -> bool {
+        false
+    }
+
+    // Check that the data source can download a dataset for the region, eg that its catalog
+    // has one. Catalogs which can't be reached don't refuse anything.
+    async fn check_region(&self, _region: &str) -> Result<(), error::Error> {
+        Ok(())
+    }
+
+    // Download the dataset, and return its path.
+    async fn download(
+        &self,
+        job: &mut Job,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error>;
+
+    // Whether the downloaded dataset must be processed before it can be indexed.
+    fn needs_processing(&self, _job: &Job) -> bool {
+        false
+    }
+
+    // Process the downloaded dataset, and return the path of the processed dataset.
+    async fn process(
+        &self,
+        _job: &mut Job,
+        _file_path: PathBuf,
+        _reporter: &mut Reporter<'_>,
+    ) -> Result<PathBuf, error::Error> {
+        Err(error::Error::MiscError {
+            details: format!("Dont know how to process {}", self.name()),
+        })
+    }
+
+    // Index the dataset in elasticsearch
+    async fn index(
+        &self,
+        job: &mut Job,
+        file_path: PathBuf,
+        reporter: &mut Reporter<'_>,
+    ) -> Result<(), error::Error>;
+
+    // Check that what we indexed is what we expected.
+    async fn validate(&self, _job: &mut Job) -> Result<(), error::Error> {
+        Ok(())
+    }
+}
+
+// The data sources we know about, by name.
+#[derive(Clone)]
+pub struct Registry {
+    sources: HashMap<&'static str, Arc<dyn DataSource>>,
+    artifacts: Artifacts, // The downloads shared by the data sources
+    tools: Arc<HashMap<&'static str, ToolCheck>>, // What we found out about the tools
+}
+
+impl Registry {
+    // A registry with all the data sources shipped with mimir_ingest.
+    pub fn new(settings: &Settings, logger: &Logger) -> Self {
+        let artifacts = Artifacts::new(settings.work.cleanup_downloads, logger.clone());
+        let mut registry = Registry {
+            sources: HashMap::new(),
+            artifacts: artifacts.clone(),
+            tools: Arc::new(HashMap::new()),
+        };
+        registry.register(Arc::new(osm::Osm {
+            artifacts: artifacts.clone(),
+        }));
+        registry.register(Arc::new(cosmogony::Cosmogony { artifacts }));
+        registry.register(Arc::new(bano::Bano {
+            concurrency: settings.bano.concurrency,
+        }));
+        registry.register(Arc::new(ntfs::Ntfs));
+        registry.register(Arc::new(gtfs::Gtfs {
+            feeds: settings.gtfs.feeds.clone(),
+        }));
+        registry.register(Arc::new(remote::Remote));
+        registry.check_tools(settings, logger);
+        registry
+    }
+
+    // Check the tools of all the data sources. A data source with a missing or incompatible
+    // tool is not available.
+    pub fn check_tools(&mut self, settings: &Settings, logger: &Logger) {
+        let mut tools = HashMap::new();
+        for source in self.sources.values() {
+            for tool in source.tools() {
+                tools
+                    .entry(*tool)
+                    .or_insert_with(|| check_tool(settings, tool));
+            }
+        }
+        for check in tools.values() {
+            match (&check.version, &check.error) {
+                (_, Some(err)) => warn!(logger, "Tool {} is not available: {}", check.name, err),
+                (Some(version), None) => {
+                    info!(logger, "Tool {} found: {}", check.name, version)
+                }
+                (None, None) => {}
+            }
+        }
+        self.tools = Arc::new(tools);
+    }
+
+    // What we found out about the tools, sorted by name.
+    pub fn tools(&self) -> Vec<&ToolCheck> {
+        let mut tools = self.tools.values().collect::<Vec<_>>();
+        tools.sort_by_key(|check| check.name.clone());
+        tools
+    }
+
+    // The version of a tool, if it is available.
+    pub fn tool_version(&self, tool: &str) -> Option<&str> {
+        self.tools
+            .get(tool)
+            .and_then(|check| check.version.as_deref())
+    }
+
+    // The versions of the tools we found.
+    pub fn tool_versions(&self) -> HashMap<String, String> {
+        self.tools
+            .values()
+            .filter_map(|check| {
+                check
+                    .version
+                    .as_ref()
+                    .map(|version| (check.name.clone(), version.clone()))
+            })
+            .collect()
+    }
+
+    // The tools of the data source which can't be used, if any.
+    pub fn missing_tools(&self, source: &dyn DataSource) -> Vec<&ToolCheck> {
+        source
+            .tools()
+            .iter()
+            .filter_map(|tool| self.tools.get(tool))
+            .filter(|check| !check.is_ok())
+            .collect()
+    }
+
+    pub fn register(&mut self, source: Arc<dyn DataSource>) {
+        self.sources.insert(source.name(), source);
+    }
+
+    pub fn artifacts(&self) -> &Artifacts {
+        &self.artifacts
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn DataSource>> {
+        self.sources.get(name).cloned()
+    }
+
+    // The data source 'data_source', provided it can produce an index of type 'index_type'.
+    pub fn find(
+        &self,
+        data_source: &str,
+        index_type: &str,
+    ) -> Result<Arc<dyn DataSource>, error::Error> {
+        let source = self.get(data_source).ok_or(error::Error::MiscError {
+            details: format!(
+                "Unknown data source '{}', expected one of {}",
+                data_source,
+                self.names().join(", ")
+            ),
+        })?;
+        if !source.index_types().contains(&index_type) {
+            return Err(error::Error::MiscError {
+                details: format!(
+                    "Cannot create a {} index using {}, only {}",
+                    index_type,
+                    data_source,
+                    source.index_types().join(", ")
+                ),
+            });
+        }
+        let missing = self.missing_tools(source.as_ref());
+        if !missing.is_empty() {
+            return Err(error::Error::MiscError {
+                details: format!(
+                    "Data source {} is not available: {}",
+                    data_source,
+                    missing
+                        .iter()
+                        .filter_map(|check| check.error.clone())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+        Ok(source)
+    }
+
+    // The names of all the data sources, sorted.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = self.sources.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("sources", &self.names())
+            .finish()
+    }
+}
@@ -0,0 +1,121 @@
+use snafu::ResultExt;
+use std::path::PathBuf;
+use url::Url;
+
+use super::error;
+
+// Resolve a pre-staged dataset (a PBF, a CSV, an NTFS directory, ...) already present on disk.
+// The path can be given either as a plain path, or as a 'file://' URL. Relative paths are taken
+// relative to the staging directory.
+// We refuse anything that, once canonicalized (ie symbolic links and '..' are resolved), is not
+// inside the staging directory, so that an API user cannot feed us an arbitrary file.
+pub fn resolve_staged_path(staging_dir: &str, path: &str) -> Result<PathBuf, error::Error> {
+    let root = std::fs::canonicalize(staging_dir).context(error::IOError {
+        details: format!("Could not resolve staging directory {}", staging_dir),
+    })?;
+
+    let path = if path.starts_with("file://") {
+        Url::parse(path)
+            .context(error::URLError {
+                details: format!("Could not parse file URL {}", path),
+            })?
+            .to_file_path()
+            .map_err(|_| error::Error::MiscError {
+                details: format!("{} is not a valid local file URL", path),
+            })?
+    } else {
+        PathBuf::from(path)
+    };
+
+    let path = if path.is_relative() {
+        root.join(path)
+    } else {
+        path
+    };
+
+    let path = std::fs::canonicalize(&path).context(error::IOError {
+        details: format!("Could not find staged dataset {}", path.display()),
+    })?;
+
+    if !path.starts_with(&root) {
+        return Err(error::Error::MiscError {
+            details: format!(
+                "Staged dataset {} is not inside the staging directory {}",
+                path.display(),
+                root.display()
+            ),
+        });
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A staging directory holding a dataset, inside a directory holding another file, which is
+    // outside the staging directory. Each test has its own.
+    fn staging(name: &str) -> (PathBuf, String) {
+        let base = std::env::temp_dir().join(format!(
+            "mimir_ingest_staged_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let staging = base.join("staging");
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join("data.csv"), "").unwrap();
+        fs::write(base.join("secret.csv"), "").unwrap();
+        (base, staging.display().to_string())
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_staging_directory() {
+        let (base, staging) = staging("inside");
+        let expected = fs::canonicalize(base.join("staging/data.csv")).unwrap();
+
+        let relative = resolve_staged_path(&staging, "data.csv").unwrap();
+        assert_eq!(relative, expected);
+
+        let absolute = format!("{}/data.csv", staging);
+        assert_eq!(resolve_staged_path(&staging, &absolute).unwrap(), expected);
+
+        let url = Url::from_file_path(&expected).unwrap();
+        assert_eq!(
+            resolve_staged_path(&staging, url.as_str()).unwrap(),
+            expected
+        );
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn refuses_paths_escaping_the_staging_directory() {
+        let (base, staging) = staging("escape");
+        let secret = fs::canonicalize(base.join("secret.csv")).unwrap();
+
+        assert!(resolve_staged_path(&staging, "../secret.csv").is_err());
+        assert!(resolve_staged_path(&staging, &secret.display().to_string()).is_err());
+        let url = Url::from_file_path(&secret).unwrap();
+        assert!(resolve_staged_path(&staging, url.as_str()).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, base.join("staging/link.csv")).unwrap();
+            assert!(resolve_staged_path(&staging, "link.csv").is_err());
+        }
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn refuses_missing_datasets() {
+        let (base, staging) = staging("missing");
+
+        assert!(resolve_staged_path(&staging, "nothing.csv").is_err());
+        assert!(resolve_staged_path("/no/such/staging/dir", "data.csv").is_err());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::settings::Settings;
+
+// The external tools run by the data sources.
+pub const OSM2MIMIR: &str = "osm2mimir";
+pub const BANO2MIMIR: &str = "bano2mimir";
+pub const OPENADDRESSES2MIMIR: &str = "openaddresses2mimir";
+pub const COSMOGONY2MIMIR: &str = "cosmogony2mimir";
+pub const NTFS2MIMIR: &str = "ntfs2mimir";
+pub const COSMOGONY: &str = "cosmogony";
+pub const GTFS2NTFS: &str = "gtfs2ntfs";
+
+// What we found out about a tool.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolCheck {
+    pub name: String,
+    pub path: PathBuf,
+    // The first line of the output of '<tool> --version'
+    pub version: Option<String>,
+    // Why the tool can't be used, if so
+    pub error: Option<String>,
+}
+
+impl ToolCheck {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// Where we expect to find a tool: cosmogony and transit_model have their own directory,
+// everything else comes from mimirsbrunn.
+pub fn tool_path(settings: &Settings, name: &str) -> PathBuf {
+    let dir = match name {
+        COSMOGONY => &settings.work.cosmogony_dir,
+        GTFS2NTFS => &settings.work.transit_model_dir,
+        _ => &settings.work.mimirsbrunn_dir,
+    };
+    let mut path = PathBuf::from(dir);
+    path.push(name);
+    path
+}
+
+// Check that the tool is an executable file, which tells its version.
+pub fn check_tool(settings: &Settings, name: &str) -> ToolCheck {
+    let path = tool_path(settings, name);
+    let mut check = ToolCheck {
+        name: String::from(name),
+        path: path.clone(),
+        version: None,
+        error: None,
+    };
+    match std::fs::metadata(&path) {
+        Err(err) => {
+            check.error = Some(format!("Could not find {}: {}", path.display(), err));
+            return check;
+        }
+        Ok(metadata) if !metadata.is_file() => {
+            check.error = Some(format!("{} is not a file", path.display()));
+            return check;
+        }
+        Ok(metadata) if metadata.permissions().mode() & 0o111 == 0 => {
+            check.error = Some(format!("{} is not executable", path.display()));
+            return check;
+        }
+        Ok(_) => {}
+    }
+    match Command::new(&path).arg("--version").output() {
+        Err(err) => {
+            check.error = Some(format!("Could not run {}: {}", path.display(), err));
+        }
+        // A tool which does not understand --version is not one we know how to drive.
+        Ok(output) if !output.status.success() => {
+            check.error = Some(format!(
+                "{} --version failed ({}), the tool is not compatible",
+                path.display(),
+                output.status
+            ));
+        }
+        Ok(output) => {
+            let version = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| String::from(line.trim()))
+                .filter(|line| !line.is_empty());
+            match version {
+                Some(version) => check.version = Some(version),
+                None => {
+                    check.error = Some(format!(
+                        "{} --version did not tell its version",
+                        path.display()
+                    ))
+                }
+            }
+        }
+    }
+    check
+}
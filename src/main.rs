@@ -1,6 +1,7 @@
 use clap::{App, Arg, SubCommand};
 use slog::{o, warn, Drain};
 
+mod doctor;
 mod init;
 mod server;
 
@@ -65,6 +66,26 @@ async fn main() -> Result<(), error::Error> {
                         .help("Settings used"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the tools used by the data sources")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("DIRECTORY")
+                        .short("c")
+                        .long("config")
+                        .help("Config directory"),
+                )
+                .arg(
+                    Arg::with_name("settings")
+                        .value_name("NAME")
+                        .short("s")
+                        .long("settings")
+                        .help("Settings used"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("test")
                 .about("Test Something")
@@ -95,6 +116,7 @@ async fn main() -> Result<(), error::Error> {
     match matches.subcommand() {
         ("run", Some(sm)) => server::run(sm, logger).await,
         ("init", Some(sm)) => init::init(sm, logger).await,
+        ("doctor", Some(sm)) => doctor::doctor(sm, logger).await,
         // ("test", Some(sm)) => test::test(sm, logger).await,
         _ => {
             warn!(logger, "Unrecognized subcommand");
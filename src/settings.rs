@@ -2,8 +2,10 @@ use clap::ArgMatches;
 use config::{Config, Environment, File};
 use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use super::error;
 
@@ -18,6 +20,9 @@ pub struct Zmq {
 pub struct Elasticsearch {
     pub host: String,
     pub port: u16,
+    // Prepended to the region to make the mimirsbrunn dataset of an index
+    #[serde(default)]
+    pub dataset_prefix: String,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -25,6 +30,109 @@ pub struct Work {
     pub working_dir: String,
     pub mimirsbrunn_dir: String,
     pub cosmogony_dir: String,
+    pub transit_model_dir: String,
+    pub staging_dir: String,
+    // Where the mimirsbrunn configurations given with the 'config_dir' option must be
+    pub config_root: String,
+    // Remove the downloaded datasets once no job is using them anymore
+    #[serde(default)]
+    pub cleanup_downloads: bool,
+}
+
+// The GTFS feeds we know about, by region
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Gtfs {
+    pub feeds: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Bano {
+    // How many departments we download at the same time for a group of regions (eg 'france')
+    pub concurrency: usize,
+}
+
+impl Default for Bano {
+    fn default() -> Self {
+        Bano { concurrency: 4 }
+    }
+}
+
+// How we deal with indexes which need other indexes for the same region (eg streets need admins)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Dependencies {
+    // How old (in seconds) an available index can be, to be used as a dependency
+    pub max_age: u64,
+    // How often (in seconds) we check on the dependencies we are waiting for
+    pub poll_interval: u64,
+    // How long (in seconds) we wait for the dependencies, before giving up
+    pub timeout: u64,
+    // The data source used to create a missing dependency, by index type
+    pub sources: HashMap<String, String>,
+}
+
+impl Default for Dependencies {
+    fn default() -> Self {
+        let mut sources = HashMap::new();
+        sources.insert(String::from("admins"), String::from("cosmogony"));
+        Dependencies {
+            max_age: 86400,
+            poll_interval: 10,
+            timeout: 86400,
+            sources,
+        }
+    }
+}
+
+// How we recognize the retries of a request creating an index.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Idempotency {
+    // How long (in seconds) we remember the key of a request
+    pub retention: u64,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency { retention: 86400 }
+    }
+}
+
+// How long the external tools may run, and what resources they may use.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Tools {
+    // The wall-clock timeout (in seconds) of each step (download, processing, indexing,
+    // validation), for all the data sources
+    #[serde(default)]
+    pub timeouts: HashMap<String, u64>,
+    // Timeouts by data source, then by step, which take precedence over the above
+    #[serde(default)]
+    pub source_timeouts: HashMap<String, HashMap<String, u64>>,
+    // The niceness of the tools (Linux only)
+    pub nice: Option<i32>,
+    // The maximum size (in MB) of the address space of the tools (Linux only)
+    pub memory_limit: Option<u64>,
+    // The CPUs the tools may run on (Linux only)
+    pub cpus: Option<Vec<usize>>,
+    // Refuse to start when a tool is missing or incompatible, rather than only disabling the
+    // data sources which need it
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl Tools {
+    pub fn timeout(&self, data_source: &str, step: &str) -> Option<Duration> {
+        self.source_timeouts
+            .get(data_source)
+            .and_then(|timeouts| timeouts.get(step))
+            .or_else(|| self.timeouts.get(step))
+            .map(|secs| Duration::from_secs(*secs))
+    }
+}
+
+// One of the indexes making up a region bundle profile
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProfileIndex {
+    pub index_type: String,
+    pub data_source: String,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -48,6 +156,19 @@ pub struct Settings {
     pub zmq: Zmq,
     pub elasticsearch: Elasticsearch,
     pub work: Work,
+    #[serde(default)]
+    pub gtfs: Gtfs,
+    #[serde(default)]
+    pub bano: Bano,
+    #[serde(default)]
+    pub dependencies: Dependencies,
+    #[serde(default)]
+    pub idempotency: Idempotency,
+    #[serde(default)]
+    pub tools: Tools,
+    // The indexes to create for a region bundle, by profile name
+    #[serde(default)]
+    pub profiles: HashMap<String, Vec<ProfileIndex>>,
 }
 
 impl Settings {
@@ -1,4 +1,7 @@
+use crate::db::model::ProvideData;
+use crate::db::Db;
 use crate::error;
+use crate::fsm::{Logs, Publisher, Registry};
 use crate::settings::Settings;
 use slog::{info, o, Logger};
 use snafu::ResultExt;
@@ -10,6 +13,9 @@ pub struct State {
     pub pool: SqlitePool,
     pub logger: Logger,
     pub settings: Settings,
+    pub registry: Registry,
+    pub publisher: Publisher,
+    pub logs: Logs,
 }
 
 impl State {
@@ -36,6 +42,16 @@ impl State {
 
         info!(logger, "db version: {:?}", row.0);
 
+        // The jobs of a previous run are gone, so they don't prevent new ones from running.
+        let mut conn = pool.conn().await.context(error::DBError {
+            details: String::from("Could not connect to the database"),
+        })?;
+        conn.reset_active_indexes()
+            .await
+            .context(error::DBProvideError {
+                details: String::from("Could not reset active indexes"),
+            })?;
+
         // I make a quick connection check with elasticsearch, cause what's the point
         // of continuing if we don't have no elasticsearch...
         let elasticsearch_endpoint = format!(
@@ -50,6 +66,26 @@ impl State {
                 ),
             })?;
 
+        // All the FSMs publish their notifications through the same socket.
+        let publisher = Publisher::new(&settings.zmq)?;
+
+        let registry = Registry::new(settings, logger);
+        let missing = registry
+            .tools()
+            .into_iter()
+            .filter(|check| !check.is_ok())
+            .map(|check| check.name.clone())
+            .collect::<Vec<_>>();
+        if settings.tools.strict && !missing.is_empty() {
+            return Err(error::Error::MiscError {
+                details: format!("Missing or incompatible tools: {}", missing.join(", ")),
+            });
+        }
+
+        // The output of the tools, followed by GraphQL subscriptions.
+        let logs = Logs::new(&settings.work.working_dir, &settings.tools)
+            .with_versions(registry.tool_versions());
+
         let logger = logger.new(
             o!("host" => String::from(&settings.service.host), "port" => settings.service.port, "database" => String::from(&settings.database.url)),
         );
@@ -58,6 +94,9 @@ impl State {
             pool,
             logger,
             settings: settings.clone(),
+            registry,
+            publisher,
+            logs,
         })
     }
 }